@@ -1,18 +1,83 @@
 use std::{
     fmt::Display,
     ops::{Add, Sub},
+    str::FromStr,
 };
 
 use bevy::prelude::Component;
 
-pub const BOARD_WIDTH: i32 = 10;
-pub const BOARD_HEIGHT: i32 = 25;
+/// Number of columns and lines of a board
+///
+/// Also a resource: the size of the board being displayed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BoardSize {
+    pub width: i32,
+    pub height: i32,
+}
+
+impl BoardSize {
+    pub const STANDARD: BoardSize = BoardSize::new(10, 25);
+    /// Pieces must be able to enter and turn on the board
+    pub const MIN: BoardSize = BoardSize::new(4, 8);
+    /// Still fits on a screen
+    pub const MAX: BoardSize = BoardSize::new(30, 40);
+
+    pub const fn new(width: i32, height: i32) -> Self {
+        Self { width, height }
+    }
+
+    /// This size, grown to be at least as wide and as high as `min`
+    pub fn at_least(&self, min: BoardSize) -> BoardSize {
+        BoardSize::new(self.width.max(min.width), self.height.max(min.height))
+    }
+
+    fn area(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+
+    /// Is the position inside the board, walls excluded?
+    pub fn contains(&self, bp: BoardPosition) -> bool {
+        bp.x >= 1 && bp.y >= 1 && bp.x <= self.width && bp.y <= self.height
+    }
+}
+
+impl Default for BoardSize {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+impl Display for BoardSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// Reads a `<width>x<height>` size, between [`BoardSize::MIN`] and
+/// [`BoardSize::MAX`]
+impl FromStr for BoardSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) = s
+            .split_once('x')
+            .ok_or_else(|| format!("expected <width>x<height>, found {:?}", s))?;
+        let width: i32 = width.trim().parse().map_err(|_| "invalid width")?;
+        let height: i32 = height.trim().parse().map_err(|_| "invalid height")?;
+        let (min, max) = (Self::MIN, Self::MAX);
+        if !(min.width..=max.width).contains(&width) || !(min.height..=max.height).contains(&height)
+        {
+            return Err(format!("the board must be between {} and {}", min, max));
+        }
+        Ok(Self::new(width, height))
+    }
+}
 
 /// Position in the Board
 ///
 /// Each square that are composing pieces have a BoardPosition
 /// component to ease computing
-#[derive(Component, Clone, Copy, Debug)]
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct BoardPosition {
     pub x: i32,
     pub y: i32,
@@ -57,13 +122,10 @@ impl BoardPosition {
         Self { x, y }
     }
 
-    fn into_idx(self) -> usize {
-        assert!(self.x >= 1);
-        assert!(self.y >= 1);
-        assert!(self.x <= BOARD_WIDTH);
-        assert!(self.y <= BOARD_HEIGHT);
+    fn into_idx(self, size: BoardSize) -> usize {
+        assert!(size.contains(self));
 
-        (self.x - 1 + (self.y - 1) * BOARD_WIDTH) as usize
+        (self.x - 1 + (self.y - 1) * size.width) as usize
     }
 }
 
@@ -74,43 +136,186 @@ impl From<(i32, i32)> for BoardPosition {
 }
 
 /// Helper struct  that detects complete lines
+#[derive(Clone)]
 pub struct Board {
-    inner: [bool; BOARD_WIDTH as usize * BOARD_HEIGHT as usize],
+    size: BoardSize,
+    inner: Vec<bool>,
 }
 
-const FULL_LINE: [bool; BOARD_WIDTH as usize] = [true; BOARD_WIDTH as usize];
+impl Default for Board {
+    fn default() -> Self {
+        Self::new(BoardSize::STANDARD)
+    }
+}
 
 impl Board {
+    /// An empty board
+    pub fn new(size: BoardSize) -> Self {
+        Self {
+            size,
+            inner: vec![false; size.area()],
+        }
+    }
+
+    pub fn size(&self) -> BoardSize {
+        self.size
+    }
+
+    fn idx(&self, bp: BoardPosition) -> usize {
+        bp.into_idx(self.size)
+    }
+
     pub fn is_line_full(&self, line: i32) -> bool {
         let start_pos = BoardPosition::new(1, line);
-        let end_post = BoardPosition::new(BOARD_WIDTH, line);
-        self.inner[start_pos.into_idx()..=end_post.into_idx()] == FULL_LINE
+        let end_post = BoardPosition::new(self.size.width, line);
+        self.inner[self.idx(start_pos)..=self.idx(end_post)]
+            .iter()
+            .all(|square| *square)
     }
 
     /// is in a wall or in a fixed square?
     pub fn is_concrete<BP: Into<BoardPosition>>(&self, bp: BP) -> bool {
         let bp: BoardPosition = bp.into();
-        if bp.x <= 0 || bp.x == BOARD_WIDTH + 1 || bp.y <= 0 {
+        if bp.x <= 0 || bp.x == self.size.width + 1 || bp.y <= 0 {
             // wall
             true
-        } else if bp.y > BOARD_HEIGHT {
+        } else if bp.y > self.size.height {
             // outide this board
             false
         } else {
-            self.inner[bp.into_idx()]
+            self.inner[self.idx(bp)]
+        }
+    }
+
+    /// Makes the square at the given position concrete.
+    ///
+    /// Positions outside the board (above it or in the walls) are ignored.
+    pub fn fill<BP: Into<BoardPosition>>(&mut self, bp: BP) {
+        let bp: BoardPosition = bp.into();
+        if self.size.contains(bp) {
+            let idx = self.idx(bp);
+            self.inner[idx] = true;
         }
     }
+
+    /// Nothing left on the board?
+    pub fn is_empty(&self) -> bool {
+        self.inner.iter().all(|square| !square)
+    }
+
+    /// All the full lines, from bottom to top
+    pub fn full_lines(&self) -> Vec<i32> {
+        (1..=self.size.height)
+            .filter(|line| self.is_line_full(*line))
+            .collect()
+    }
+
+    /// Removes the given lines, everything above them falls down
+    pub fn remove_lines(&mut self, lines: &[i32]) {
+        let width = self.size.width;
+        let row = |y: i32| ((y - 1) * width) as usize..(y * width) as usize;
+        let mut dest = 1;
+        for y in 1..=self.size.height {
+            if lines.contains(&y) {
+                continue;
+            }
+            if dest != y {
+                self.inner.copy_within(row(y), row(dest).start);
+            }
+            dest += 1;
+        }
+        for y in dest..=self.size.height {
+            self.inner[row(y)].fill(false);
+        }
+    }
+
+    /// Empties the given lines, then lets each group of connected squares
+    /// fall until it lands on the floor or on another group.
+    ///
+    /// Returns the squares that have fallen, at their position once the
+    /// lines are emptied, with the number of lines they went down.
+    pub fn cascade(&mut self, lines: &[i32]) -> Vec<(BoardPosition, i32)> {
+        for y in lines {
+            for x in 1..=self.size.width {
+                let idx = self.idx(BoardPosition::new(x, *y));
+                self.inner[idx] = false;
+            }
+        }
+        let mut clusters: Vec<(Vec<BoardPosition>, i32)> = self
+            .clusters()
+            .into_iter()
+            .map(|cluster| (cluster, 0))
+            .collect();
+        // the lowest groups land first, the others can land on them
+        clusters.sort_by_key(|(cluster, _)| cluster.iter().map(|bp| bp.y).min());
+        // a group can be held by another one under it until that one falls
+        let mut moved = true;
+        while moved {
+            moved = false;
+            for (cluster, fall) in &mut clusters {
+                let at = |bp: &BoardPosition, fall: i32| BoardPosition::new(bp.x, bp.y - fall);
+                for bp in cluster.iter() {
+                    let idx = self.idx(at(bp, *fall));
+                    self.inner[idx] = false;
+                }
+                while cluster
+                    .iter()
+                    .all(|bp| !self.is_concrete(at(bp, *fall + 1)))
+                {
+                    *fall += 1;
+                    moved = true;
+                }
+                for bp in cluster.iter() {
+                    let idx = self.idx(at(bp, *fall));
+                    self.inner[idx] = true;
+                }
+            }
+        }
+        clusters
+            .into_iter()
+            .filter(|(_, fall)| *fall > 0)
+            .flat_map(|(cluster, fall)| cluster.into_iter().map(move |bp| (bp, fall)))
+            .collect()
+    }
+
+    /// The groups of squares touching each other by a side
+    fn clusters(&self) -> Vec<Vec<BoardPosition>> {
+        let mut seen = vec![false; self.size.area()];
+        let mut clusters = Vec::new();
+        for start in 0..self.inner.len() {
+            if !self.inner[start] || seen[start] {
+                continue;
+            }
+            seen[start] = true;
+            let mut cluster = Vec::new();
+            let mut to_visit = vec![BoardPosition::new(
+                start as i32 % self.size.width + 1,
+                start as i32 / self.size.width + 1,
+            )];
+            while let Some(bp) = to_visit.pop() {
+                cluster.push(bp);
+                for neighbour in [bp + (1, 0), bp - (1, 0), bp + (0, 1), bp - (0, 1)] {
+                    if !self.size.contains(neighbour) {
+                        continue;
+                    }
+                    let idx = self.idx(neighbour);
+                    if self.inner[idx] && !seen[idx] {
+                        seen[idx] = true;
+                        to_visit.push(neighbour);
+                    }
+                }
+            }
+            clusters.push(cluster);
+        }
+        clusters
+    }
 }
 
 impl FromIterator<BoardPosition> for Board {
     fn from_iter<T: IntoIterator<Item = BoardPosition>>(iter: T) -> Self {
-        let mut ret = Board {
-            inner: [false; BOARD_WIDTH as usize * BOARD_HEIGHT as usize],
-        };
+        let mut ret = Board::default();
         for bp in iter {
-            if bp.x > 0 && bp.y > 0 && bp.x <= BOARD_WIDTH {
-                ret.inner[bp.into_idx()] = true;
-            }
+            ret.fill(bp);
         }
         ret
     }
@@ -118,10 +323,10 @@ impl FromIterator<BoardPosition> for Board {
 
 impl Display for Board {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for y in (1..BOARD_HEIGHT).rev() {
-            for x in 1..=BOARD_WIDTH {
+        for y in (1..self.size.height).rev() {
+            for x in 1..=self.size.width {
                 let bp = BoardPosition::new(x, y);
-                if self.inner[bp.into_idx()] {
+                if self.inner[self.idx(bp)] {
                     f.write_str("*")?;
                 } else {
                     f.write_str(".")?;
@@ -132,3 +337,70 @@ impl Display for Board {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(squares: &[(i32, i32)]) -> Board {
+        squares.iter().map(|bp| BoardPosition::from(*bp)).collect()
+    }
+
+    fn full_line(y: i32) -> Vec<(i32, i32)> {
+        (1..=BoardSize::STANDARD.width).map(|x| (x, y)).collect()
+    }
+
+    /// The squares that have fallen, sorted by column then line
+    fn sorted(mut falls: Vec<(BoardPosition, i32)>) -> Vec<((i32, i32), i32)> {
+        falls.sort_by_key(|(bp, _)| (bp.x, bp.y));
+        falls
+            .into_iter()
+            .map(|(bp, fall)| ((bp.x, bp.y), fall))
+            .collect()
+    }
+
+    #[test]
+    fn groups_fall_to_the_floor() {
+        let mut board = filled(&[full_line(1), vec![(1, 2), (5, 4), (5, 5)]].concat());
+        let falls = board.cascade(&[1]);
+        assert_eq!(sorted(falls), [((1, 2), 1), ((5, 4), 3), ((5, 5), 3)]);
+        assert_eq!(
+            board.to_string(),
+            filled(&[(1, 1), (5, 1), (5, 2)]).to_string()
+        );
+    }
+
+    #[test]
+    fn groups_land_on_each_other() {
+        let mut board = filled(&[full_line(1), vec![(3, 3), (3, 5), (3, 6)]].concat());
+        let falls = board.cascade(&[1]);
+        assert_eq!(sorted(falls), [((3, 3), 2), ((3, 5), 3), ((3, 6), 3)]);
+        assert_eq!(
+            board.to_string(),
+            filled(&[(3, 1), (3, 2), (3, 3)]).to_string()
+        );
+    }
+
+    #[test]
+    fn landed_groups_stay() {
+        let mut board = filled(&[vec![(1, 1), (2, 1)], full_line(2)].concat());
+        assert!(board.cascade(&[2]).is_empty());
+        assert_eq!(board.to_string(), filled(&[(1, 1), (2, 1)]).to_string());
+    }
+
+    #[test]
+    fn falling_groups_can_complete_lines() {
+        // the line at the bottom only misses the square falling in its hole
+        let line: Vec<_> = (2..=BoardSize::STANDARD.width).map(|x| (x, 2)).collect();
+        let mut board = filled(&[full_line(1), line, vec![(1, 3)]].concat());
+        board.cascade(&[1]);
+        assert_eq!(board.full_lines(), [1]);
+    }
+
+    #[test]
+    fn naive_clears_move_everything_down() {
+        let mut board = filled(&[full_line(1), vec![(1, 3)], full_line(4), vec![(2, 6)]].concat());
+        board.remove_lines(&[1, 4]);
+        assert_eq!(board.to_string(), filled(&[(1, 2), (2, 4)]).to_string());
+    }
+}
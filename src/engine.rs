@@ -0,0 +1,1382 @@
+//! The rules of the game, independent of Bevy.
+//!
+//! A [`Game`] is driven by player [`Input`]s (see [`Game::step`]) and by the
+//! time passing (see [`Game::tick`]). Everything that happens on the board is
+//! reported as [`GameEvent`]s so a front end only has to render them: the
+//! Bevy systems in `main.rs` are such a front end, but a `Game` can be played
+//! without any window, which is handy for simulations.
+
+use std::{collections::VecDeque, fmt::Display, time::Duration};
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    board::{Board, BoardPosition, BoardSize},
+    garbage::Garbage,
+    gravity::{GravityCurve, FRAMES_PER_SECOND},
+    master::{self, Grade, Grading, SectionTiming},
+    piece::{Orientation, Piece, PieceType, Rotation},
+    puzzle::Puzzle,
+    randomizer::{PieceGenerator, Randomizer, SequenceGenerator},
+    scoring::{LockResult, Scoring, ScoringRule},
+};
+
+/// Gravity while the soft drop is active, unless the level is faster
+const SOFT_DROP_GRAVITY: f64 = 0.5;
+/// How many times moving or rotating a piece on the ground restarts the lock
+/// delay, with [`LockReset::Move`]
+const MAX_LOCK_RESETS: u32 = 15;
+/// Maximum number of upcoming pieces a game can show
+pub const MAX_NEXT_PIECES: usize = 6;
+/// Time between the removal of complete lines and the spawn of the next piece
+const LINE_CLEAR_DELAY: Duration = Duration::from_millis(200);
+/// Time given to the squares to fall after a cascade clear, before the lines
+/// they complete are cleared
+const CASCADE_FALL_DELAY: Duration = Duration::from_millis(200);
+
+/// What a player can do with the current piece
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    Left,
+    Right,
+    RotateClock,
+    RotateAnti,
+    /// Swap the current piece with the held one
+    Hold,
+    /// Move the piece down as far as possible and lock it
+    HardDrop,
+    /// Start moving down faster
+    SoftDrop,
+    /// Back to the normal speed
+    ReleaseSoftDrop,
+}
+
+/// Something that happened on the board
+#[derive(Clone, Debug)]
+pub enum GameEvent {
+    /// A new piece entered the board
+    PieceSpawned(Piece),
+    /// The current piece has moved or rotated
+    PieceMoved(Piece),
+    /// The current piece hit something concrete and is now part of the board
+    PieceLocked(Piece),
+    /// The current piece left the board to be held
+    PieceHeld(PieceType),
+    /// These lines were complete and have been removed from the board
+    LinesCleared(Vec<i32>),
+    /// The whole stack has been removed from the board
+    BoardCleared,
+    /// Instead of everything above the cleared lines going down, only these
+    /// squares fall by the given number of lines
+    ClustersFell(Vec<(BoardPosition, i32)>),
+    /// Garbage squares have been put on the board
+    GarbageAdded(Vec<BoardPosition>),
+    /// The squares of the puzzle have been put on the board
+    PuzzleLoaded(Vec<BoardPosition>),
+    /// Lines have been cleared or a T-spin has been done without clearing lines
+    Clear(ClearKind),
+    /// Nothing more can be played
+    GameOver(GameEnd),
+}
+
+/// Why a game is over
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameEnd {
+    /// The new piece cannot enter the board
+    ToppedOut,
+    /// The line goal of the game has been reached, all the garbage has
+    /// been cleared or the puzzle has been solved
+    GoalReached,
+    /// The time limit of the game has expired
+    TimeUp,
+    /// Every piece of the puzzle has been played without solving it
+    OutOfPieces,
+}
+
+/// What has been achieved by locking a piece
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClearKind {
+    Single,
+    Double,
+    Triple,
+    Tetris,
+    MiniTSpin,
+    MiniTSpinSingle,
+    MiniTSpinDouble,
+    TSpin,
+    TSpinSingle,
+    TSpinDouble,
+    TSpinTriple,
+}
+
+/// How a T piece has been rotated into place
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+impl ClearKind {
+    /// Number of lines cleared
+    pub fn lines(&self) -> usize {
+        match self {
+            ClearKind::MiniTSpin | ClearKind::TSpin => 0,
+            ClearKind::Single | ClearKind::MiniTSpinSingle | ClearKind::TSpinSingle => 1,
+            ClearKind::Double | ClearKind::MiniTSpinDouble | ClearKind::TSpinDouble => 2,
+            ClearKind::Triple | ClearKind::TSpinTriple => 3,
+            ClearKind::Tetris => 4,
+        }
+    }
+
+    /// Tetrises and T-spins clearing lines make back-to-back chains
+    pub fn is_difficult(&self) -> bool {
+        !matches!(
+            self,
+            ClearKind::Single
+                | ClearKind::Double
+                | ClearKind::Triple
+                | ClearKind::MiniTSpin
+                | ClearKind::TSpin
+        )
+    }
+
+    fn new(lines: usize, spin: Spin) -> Option<Self> {
+        match (spin, lines) {
+            (Spin::None, 0) => None,
+            (Spin::None, 1) => Some(ClearKind::Single),
+            (Spin::None, 2) => Some(ClearKind::Double),
+            (Spin::None, 3) => Some(ClearKind::Triple),
+            (Spin::None, _) => Some(ClearKind::Tetris),
+            (Spin::Mini, 0) => Some(ClearKind::MiniTSpin),
+            (Spin::Mini, 1) => Some(ClearKind::MiniTSpinSingle),
+            (Spin::Mini, _) => Some(ClearKind::MiniTSpinDouble),
+            (Spin::Full, 0) => Some(ClearKind::TSpin),
+            (Spin::Full, 1) => Some(ClearKind::TSpinSingle),
+            (Spin::Full, 2) => Some(ClearKind::TSpinDouble),
+            (Spin::Full, _) => Some(ClearKind::TSpinTriple),
+        }
+    }
+}
+
+impl Display for ClearKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ClearKind::Single => "SINGLE",
+            ClearKind::Double => "DOUBLE",
+            ClearKind::Triple => "TRIPLE",
+            ClearKind::Tetris => "TETRIS",
+            ClearKind::MiniTSpin => "MINI T-SPIN",
+            ClearKind::MiniTSpinSingle => "MINI T-SPIN\nSINGLE",
+            ClearKind::MiniTSpinDouble => "MINI T-SPIN\nDOUBLE",
+            ClearKind::TSpin => "T-SPIN",
+            ClearKind::TSpinSingle => "T-SPIN\nSINGLE",
+            ClearKind::TSpinDouble => "T-SPIN\nDOUBLE",
+            ClearKind::TSpinTriple => "T-SPIN\nTRIPLE",
+        })
+    }
+}
+
+/// What restarts the lock delay of a piece on the ground
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LockReset {
+    /// Each move or rotation, up to 15 times until the piece goes down
+    /// further (guideline)
+    #[default]
+    Move,
+    /// Only going down one more line
+    Step,
+    /// Nothing: the delay runs from the first time the piece touches the ground
+    Never,
+}
+
+/// What happens when a new piece cannot enter the board
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TopOut {
+    #[default]
+    GameOver,
+    /// The board is emptied and the game goes on
+    ClearBoard,
+}
+
+/// How the level changes during a game
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LevelProgression {
+    /// A new level every 10 lines
+    #[default]
+    Lines,
+    /// The level never changes
+    Fixed,
+    /// Each piece and each line cleared add a level, but the last level of
+    /// each section of 100 needs a line clear. The delays of the pieces
+    /// depend on the section.
+    Sections,
+}
+
+/// What happens to the squares above the cleared lines
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClearGravity {
+    /// Everything goes down by the number of lines cleared below
+    #[default]
+    Naive,
+    /// Each group of connected squares falls until it lands, which can
+    /// complete more lines: a chain, each clear scoring more than the last
+    Cascade,
+}
+
+/// The rules a game is played with
+#[derive(Clone, Debug)]
+pub struct GameConfig {
+    pub randomizer: Randomizer,
+    pub scoring: Scoring,
+    pub gravity: GravityCurve,
+    /// Level the game starts at
+    pub start_level: usize,
+    pub level_progression: LevelProgression,
+    pub top_out: TopOut,
+    pub clear_gravity: ClearGravity,
+    /// Games with the same seed and the same inputs play exactly the same
+    pub seed: u64,
+    /// Time a piece can stay on the ground before being locked, unless the
+    /// section gives it
+    pub lock_delay: Duration,
+    pub lock_reset: LockReset,
+    /// How many upcoming pieces are shown, from 1 to [`MAX_NEXT_PIECES`]
+    pub next_pieces: usize,
+    /// The game ends once this many lines have been cleared
+    pub line_goal: Option<usize>,
+    /// The game ends once it has been played for this long
+    pub time_limit: Option<Duration>,
+    /// Rows to clear at the bottom of the board, the game ends when they are
+    /// all cleared
+    pub garbage: Option<Garbage>,
+    /// The board, pieces and goal of the game instead of the randomizer
+    pub puzzle: Option<Puzzle>,
+    /// Are the pieces made of blocks of 2x2 squares?
+    pub big: bool,
+    /// Puzzles are played on a board as wide as their rows instead
+    pub board_size: BoardSize,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            randomizer: Randomizer::default(),
+            scoring: Scoring::default(),
+            gravity: GravityCurve::default(),
+            start_level: 1,
+            level_progression: LevelProgression::default(),
+            top_out: TopOut::default(),
+            clear_gravity: ClearGravity::default(),
+            seed: rand::random(),
+            lock_delay: Duration::from_millis(500),
+            lock_reset: LockReset::default(),
+            next_pieces: 5,
+            line_goal: None,
+            time_limit: None,
+            garbage: None,
+            puzzle: None,
+            big: false,
+            board_size: BoardSize::default(),
+        }
+    }
+}
+
+enum Phase {
+    /// The current piece is going down
+    Falling,
+    /// Waiting before spawning the next piece, while the cleared lines
+    /// disappear or during the entry delay
+    Entry(Duration),
+    GameOver(GameEnd),
+}
+
+pub struct Game {
+    config: GameConfig,
+    board: Board,
+    piece: Option<Piece>,
+    generator: Box<dyn PieceGenerator>,
+    scoring: Box<dyn ScoringRule>,
+    /// every random choice of the game comes from this generator
+    rng: ChaCha8Rng,
+    /// upcoming pieces, the first one is the next to be spawned
+    next_pieces: VecDeque<PieceType>,
+    held_piece: Option<PieceType>,
+    /// the piece can be held only once until it is locked
+    can_hold: bool,
+    phase: Phase,
+    /// lines the piece has to go down, the fractional part is kept for the
+    /// next ticks
+    fall: f64,
+    /// time spent on the ground by the current piece
+    lock_elapsed: Duration,
+    lock_resets: u32,
+    /// lowest line reached by the current piece
+    lowest_line: i32,
+    /// the kick used if the last move of the piece was a rotation
+    last_rotation: Option<usize>,
+    soft_drop: bool,
+    score: usize,
+    /// consecutive pieces that have cleared lines, minus one
+    combo: Option<usize>,
+    /// consecutive difficult clears, minus one
+    back_to_back: Option<usize>,
+    /// clears made since the last piece was locked, the ones after the
+    /// first are made by falling squares
+    chain: usize,
+    level: usize,
+    lines: usize,
+    grading: Grading,
+    /// garbage rows still on the board, they are always the lowest ones
+    garbage_left: usize,
+    puzzle_solved: bool,
+    /// time played, until the game is over
+    elapsed: Duration,
+    events: Vec<GameEvent>,
+}
+
+impl Game {
+    pub fn new(mut config: GameConfig) -> Self {
+        if let Some(puzzle) = &config.puzzle {
+            config.board_size = puzzle.board_size;
+        }
+        let mut generator: Box<dyn PieceGenerator> = match &config.puzzle {
+            Some(puzzle) => Box::new(SequenceGenerator::new(&puzzle.pieces)),
+            None => config.randomizer.generator(),
+        };
+        let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+        let next_pieces = (0..config.next_pieces.clamp(1, MAX_NEXT_PIECES))
+            .map_while(|_| generator.next_piece(&mut rng))
+            .collect();
+        let scoring = config.scoring.rule();
+        let level = config.start_level;
+        let mut game = Self {
+            board: Board::new(config.board_size),
+            config,
+            piece: None,
+            generator,
+            scoring,
+            rng,
+            next_pieces,
+            held_piece: None,
+            can_hold: true,
+            phase: Phase::Falling,
+            fall: 0.,
+            lock_elapsed: Duration::ZERO,
+            lock_resets: 0,
+            lowest_line: 0,
+            last_rotation: None,
+            soft_drop: false,
+            score: 0,
+            combo: None,
+            back_to_back: None,
+            chain: 0,
+            level,
+            lines: 0,
+            grading: Grading::default(),
+            garbage_left: 0,
+            puzzle_solved: false,
+            elapsed: Duration::ZERO,
+            events: Vec::new(),
+        };
+        if let Some(garbage) = game.config.garbage {
+            game.add_garbage(garbage);
+        }
+        if let Some(puzzle) = &game.config.puzzle {
+            let positions = puzzle.board.clone();
+            for pos in &positions {
+                game.board.fill(*pos);
+            }
+            game.events.push(GameEvent::PuzzleLoaded(positions));
+        }
+        game.spawn_next_piece();
+        game
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.config.seed
+    }
+
+    pub fn next_pieces(&self) -> &VecDeque<PieceType> {
+        &self.next_pieces
+    }
+
+    /// Where the current piece would land if dropped
+    pub fn ghost_piece(&self) -> Option<Piece> {
+        self.piece.map(|piece| Piece {
+            position: piece.position + (0, -self.drop_distance(&piece)),
+            ..piece
+        })
+    }
+
+    pub fn score(&self) -> usize {
+        self.score
+    }
+
+    /// Consecutive pieces that have cleared lines, minus one
+    pub fn combo(&self) -> usize {
+        self.combo.unwrap_or(0)
+    }
+
+    /// Clears in a row made by the last piece and the squares falling
+    /// after it, only cascades make more than one
+    pub fn chain(&self) -> usize {
+        self.chain
+    }
+
+    /// Consecutive difficult clears, minus one
+    pub fn back_to_back(&self) -> usize {
+        self.back_to_back.unwrap_or(0)
+    }
+
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Time left before the time limit expires
+    pub fn remaining(&self) -> Option<Duration> {
+        self.config
+            .time_limit
+            .map(|time_limit| time_limit.saturating_sub(self.elapsed))
+    }
+
+    /// Grade earned so far, only in games played by sections
+    pub fn grade(&self) -> Option<Grade> {
+        (self.config.level_progression == LevelProgression::Sections)
+            .then(|| self.grading.grade(self.level, self.score))
+    }
+
+    pub fn puzzle(&self) -> Option<&Puzzle> {
+        self.config.puzzle.as_ref()
+    }
+
+    pub fn board_size(&self) -> BoardSize {
+        self.board.size()
+    }
+
+    /// Why the game is over, `None` while it goes on
+    pub fn end(&self) -> Option<GameEnd> {
+        match self.phase {
+            Phase::GameOver(end) => Some(end),
+            _ => None,
+        }
+    }
+
+    /// Events that happened since the last call
+    pub fn drain_events(&mut self) -> impl Iterator<Item = GameEvent> + '_ {
+        self.events.drain(..)
+    }
+
+    /// Applies a player input to the current piece
+    pub fn step(&mut self, input: Input) {
+        match input {
+            Input::Left => {
+                self.try_move(-1, 0);
+            }
+            Input::Right => {
+                self.try_move(1, 0);
+            }
+            Input::RotateClock => {
+                self.try_rotate(Rotation::Clock);
+            }
+            Input::RotateAnti => {
+                self.try_rotate(Rotation::Anti);
+            }
+            Input::Hold => self.hold(),
+            Input::HardDrop => self.hard_drop(),
+            Input::SoftDrop => self.soft_drop = true,
+            Input::ReleaseSoftDrop => self.soft_drop = false,
+        }
+    }
+
+    /// Lets the time pass: moves the current piece down, spawns new pieces...
+    pub fn tick(&mut self, delta: Duration) {
+        if !matches!(self.phase, Phase::GameOver(_)) {
+            self.elapsed += delta;
+            if let Some(time_limit) = self.config.time_limit {
+                if self.elapsed >= time_limit {
+                    self.elapsed = time_limit;
+                    self.end_game(GameEnd::TimeUp);
+                    return;
+                }
+            }
+        }
+        match self.phase {
+            Phase::Falling => {
+                let piece = match self.piece {
+                    Some(piece) => piece,
+                    None => return,
+                };
+                if self.is_on_ground(&piece) {
+                    // the piece can still be moved until the lock delay expires
+                    self.fall = 0.;
+                    self.lock_elapsed += delta;
+                    if self.lock_elapsed >= self.timing().lock_delay {
+                        self.lock_piece();
+                    }
+                    return;
+                }
+                self.fall += self.gravity() * FRAMES_PER_SECOND * delta.as_secs_f64();
+                // at high gravity the piece goes down several lines at once
+                while self.fall >= 1. {
+                    self.fall -= 1.;
+                    if !self.try_move(0, -1) {
+                        self.fall = 0.;
+                        break;
+                    }
+                    if self.soft_drop {
+                        self.score += self.scoring.soft_drop();
+                    }
+                }
+            }
+            Phase::Entry(remaining) => {
+                if delta >= remaining {
+                    self.end_entry();
+                } else {
+                    self.phase = Phase::Entry(remaining - delta);
+                }
+            }
+            Phase::GameOver(_) => (),
+        }
+    }
+
+    /// can the piece be there?
+    fn fits(&self, piece: &Piece) -> bool {
+        piece
+            .positions()
+            .into_iter()
+            .all(|pos| !self.board.is_concrete(pos))
+    }
+
+    /// is something concrete right below the piece?
+    fn is_on_ground(&self, piece: &Piece) -> bool {
+        !self.fits(&Piece {
+            position: piece.position + (0, -1),
+            ..*piece
+        })
+    }
+
+    /// How many lines the piece can go down before hitting something concrete
+    fn drop_distance(&self, piece: &Piece) -> i32 {
+        let mut distance = 0;
+        while self.fits(&Piece {
+            position: piece.position + (0, -distance - 1),
+            ..*piece
+        }) {
+            distance += 1;
+        }
+        distance
+    }
+
+    /// Moves the current piece, returns false if something is in the way
+    fn try_move(&mut self, dx: i32, dy: i32) -> bool {
+        let mut piece = match self.piece {
+            Some(piece) => piece,
+            None => return false,
+        };
+        // big pieces move sideways by a whole block
+        piece.position = piece.position + (dx * piece.scale(), dy);
+        if !self.fits(&piece) {
+            return false;
+        }
+        self.piece = Some(piece);
+        self.events.push(GameEvent::PieceMoved(piece));
+        self.restart_lock_delay(&piece);
+        self.last_rotation = None;
+        true
+    }
+
+    /// Rotates the current piece, kicking it away from walls and squares
+    /// if needed. Returns false if no kick makes the piece fit.
+    fn try_rotate(&mut self, rotation: Rotation) -> bool {
+        let piece = match self.piece {
+            Some(piece) => piece,
+            None => return false,
+        };
+        let orientation = piece.orientation.apply_rotation(rotation);
+        let kicked = piece
+            .piece_type
+            .kicks(piece.orientation, orientation)
+            .iter()
+            .map(|kick| Piece {
+                orientation,
+                position: piece.block(*kick),
+                ..piece
+            })
+            .enumerate()
+            .find(|(_, kicked)| self.fits(kicked));
+        match kicked {
+            Some((kick, kicked)) => {
+                self.piece = Some(kicked);
+                self.events.push(GameEvent::PieceMoved(kicked));
+                self.restart_lock_delay(&kicked);
+                self.last_rotation = Some(kick);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Called each time the piece has moved or rotated
+    fn restart_lock_delay(&mut self, piece: &Piece) {
+        let went_lower = piece.position.y < self.lowest_line;
+        if went_lower {
+            self.lowest_line = piece.position.y;
+        }
+        match self.config.lock_reset {
+            LockReset::Move => {
+                if went_lower {
+                    self.lock_resets = 0;
+                    self.lock_elapsed = Duration::ZERO;
+                } else if self.lock_resets < MAX_LOCK_RESETS {
+                    self.lock_resets += 1;
+                    self.lock_elapsed = Duration::ZERO;
+                }
+            }
+            LockReset::Step => {
+                if went_lower {
+                    self.lock_elapsed = Duration::ZERO;
+                }
+            }
+            LockReset::Never => (),
+        }
+    }
+
+    fn hard_drop(&mut self) {
+        let mut piece = match self.piece {
+            Some(piece) => piece,
+            None => return,
+        };
+        let distance = self.drop_distance(&piece);
+        if distance > 0 {
+            piece.position = piece.position + (0, -distance);
+            self.piece = Some(piece);
+            self.last_rotation = None;
+            self.events.push(GameEvent::PieceMoved(piece));
+            self.score += self.scoring.hard_drop() * distance as usize;
+        }
+        self.lock_piece();
+    }
+
+    /// Detects T-spins with the 3-corner rule: the last move of the T piece
+    /// must be a rotation and 3 of the corners around its center must be
+    /// concrete.
+    ///
+    /// It is a mini T-spin unless both corners the T is pointing to are
+    /// concrete, or the last kick of the rotation table has been used.
+    fn spin(&self, piece: &Piece) -> Spin {
+        let kick = match self.last_rotation {
+            Some(kick) if piece.piece_type == PieceType::T => kick,
+            _ => return Spin::None,
+        };
+        let is_concrete = |corner: &(i32, i32)| self.board.is_concrete(piece.block(*corner));
+        let corners = [(-1, 1), (1, 1), (1, -1), (-1, -1)];
+        if corners.iter().filter(|corner| is_concrete(corner)).count() < 3 {
+            return Spin::None;
+        }
+        let front_corners = match piece.orientation {
+            Orientation::Up => [(-1, 1), (1, 1)],
+            Orientation::Right => [(1, 1), (1, -1)],
+            Orientation::Bottom => [(1, -1), (-1, -1)],
+            Orientation::Left => [(-1, -1), (-1, 1)],
+        };
+        if front_corners.iter().all(is_concrete) || kick == 4 {
+            Spin::Full
+        } else {
+            Spin::Mini
+        }
+    }
+
+    fn lock_piece(&mut self) {
+        let piece = match self.piece.take() {
+            Some(piece) => piece,
+            None => return,
+        };
+        let spin = self.spin(&piece);
+        for pos in piece.positions() {
+            self.board.fill(pos);
+        }
+        self.events.push(GameEvent::PieceLocked(piece));
+        // the delays of the section the piece was locked in
+        let timing = self.timing();
+        // the soft drop only lasts for one piece
+        self.soft_drop = false;
+        self.fall = 0.;
+        self.can_hold = true;
+
+        let full_lines = self.board.full_lines();
+        let lines_cleared = !full_lines.is_empty();
+        self.chain = usize::from(lines_cleared);
+        self.clear_lines(full_lines, spin);
+        if self.is_goal_reached() {
+            // a puzzle can be solved by a T-spin without lines
+            self.end_game(GameEnd::GoalReached);
+        } else if lines_cleared {
+            self.phase = Phase::Entry(timing.line_clear + timing.line_are + self.fall_delay());
+        } else if timing.are.is_zero() {
+            self.spawn_next_piece();
+        } else {
+            self.phase = Phase::Entry(timing.are);
+        }
+    }
+
+    /// Removes the full lines and scores them
+    fn clear_lines(&mut self, full_lines: Vec<i32>, spin: Spin) {
+        let clear_kind = ClearKind::new(full_lines.len(), spin);
+        let lines_cleared = !full_lines.is_empty();
+        if lines_cleared {
+            let garbage_cleared = full_lines
+                .iter()
+                .filter(|line| **line <= self.garbage_left as i32)
+                .count();
+            self.garbage_left -= garbage_cleared;
+            match self.config.clear_gravity {
+                ClearGravity::Naive => {
+                    self.board.remove_lines(&full_lines);
+                    self.events.push(GameEvent::LinesCleared(full_lines));
+                }
+                ClearGravity::Cascade => {
+                    let falls = self.board.cascade(&full_lines);
+                    self.events.push(GameEvent::LinesCleared(full_lines));
+                    self.events.push(GameEvent::ClustersFell(falls));
+                }
+            }
+        }
+        let perfect_clear = lines_cleared && self.board.is_empty();
+        self.increase_score_and_level(clear_kind, perfect_clear);
+        if let Some(puzzle) = &self.config.puzzle {
+            self.puzzle_solved |= puzzle
+                .goal
+                .is_reached(self.lines, clear_kind, perfect_clear);
+        }
+        if let Some(clear_kind) = clear_kind {
+            self.events.push(GameEvent::Clear(clear_kind));
+        }
+    }
+
+    /// The wait before the next piece is over, unless the squares that have
+    /// fallen after a cascade clear have completed more lines
+    fn end_entry(&mut self) {
+        let full_lines = self.board.full_lines();
+        // always empty without cascades: every full line is removed at once
+        if full_lines.is_empty() {
+            self.spawn_next_piece();
+            return;
+        }
+        self.chain += 1;
+        self.clear_lines(full_lines, Spin::None);
+        if self.is_goal_reached() {
+            self.end_game(GameEnd::GoalReached);
+        } else {
+            self.phase = Phase::Entry(self.timing().line_clear + self.fall_delay());
+        }
+    }
+
+    /// Time given to the squares to fall after a clear, before the lines
+    /// they complete are cleared
+    fn fall_delay(&self) -> Duration {
+        match self.config.clear_gravity {
+            ClearGravity::Naive => Duration::ZERO,
+            ClearGravity::Cascade => CASCADE_FALL_DELAY,
+        }
+    }
+
+    fn increase_score_and_level(&mut self, clear_kind: Option<ClearKind>, perfect_clear: bool) {
+        let lines = clear_kind.map_or(0, |clear_kind| clear_kind.lines());
+        // the clears of a cascade chain belong to the piece starting it
+        let locked = self.chain <= 1;
+        let mut back_to_back = false;
+        if locked {
+            self.combo = match lines {
+                0 => None,
+                _ => Some(self.combo.map_or(0, |combo| combo + 1)),
+            };
+            match clear_kind {
+                // T-spins without lines do not break the chain
+                Some(clear_kind) if lines > 0 => {
+                    if clear_kind.is_difficult() {
+                        back_to_back = self.back_to_back.is_some();
+                        self.back_to_back = Some(self.back_to_back.map_or(0, |b2b| b2b + 1));
+                    } else {
+                        self.back_to_back = None;
+                    }
+                }
+                _ => (),
+            }
+        }
+        let points = self.scoring.lock(&LockResult {
+            clear_kind,
+            level: self.level,
+            combo: self.combo,
+            back_to_back,
+            perfect_clear,
+        });
+        // each clear of a chain is worth more than the previous one
+        self.score += points * self.chain.max(1);
+        self.lines += lines;
+        let previous_level = self.level;
+        match self.config.level_progression {
+            LevelProgression::Lines => self.level = self.config.start_level + self.lines / 10,
+            LevelProgression::Fixed => (),
+            LevelProgression::Sections => {
+                if locked && self.level % 100 != 99 && self.level < master::MAX_LEVEL - 1 {
+                    self.level += 1;
+                }
+                self.level = (self.level + lines).min(master::MAX_LEVEL);
+                self.grading
+                    .update(previous_level, self.level, self.score, self.elapsed);
+            }
+        }
+    }
+
+    fn is_goal_reached(&self) -> bool {
+        let lines_done = self.config.line_goal.is_some_and(|goal| self.lines >= goal);
+        let garbage_done = self.config.garbage.is_some() && self.garbage_left == 0;
+        let levels_done = self.config.level_progression == LevelProgression::Sections
+            && self.level >= master::MAX_LEVEL;
+        lines_done || garbage_done || levels_done || self.puzzle_solved
+    }
+
+    /// Delays of the pieces at the current level
+    fn timing(&self) -> SectionTiming {
+        match self.config.level_progression {
+            LevelProgression::Sections => master::section_timing(self.level),
+            LevelProgression::Lines | LevelProgression::Fixed => SectionTiming {
+                are: Duration::ZERO,
+                line_are: Duration::ZERO,
+                lock_delay: self.config.lock_delay,
+                line_clear: LINE_CLEAR_DELAY,
+            },
+        }
+    }
+
+    fn add_garbage(&mut self, garbage: Garbage) {
+        let positions = garbage.generate(&mut self.rng, self.board.size());
+        for pos in &positions {
+            self.board.fill(*pos);
+        }
+        self.garbage_left = garbage.rows_on(self.board.size());
+        self.events.push(GameEvent::GarbageAdded(positions));
+    }
+
+    /// Lines per frame the current piece is going down
+    fn gravity(&self) -> f64 {
+        let gravity = self.config.gravity.gravity(self.level);
+        if self.soft_drop {
+            gravity.max(SOFT_DROP_GRAVITY)
+        } else {
+            gravity
+        }
+    }
+
+    fn hold(&mut self) {
+        // the last piece of a puzzle has nothing to be swapped with
+        if !self.can_hold || (self.held_piece.is_none() && self.next_pieces.is_empty()) {
+            return;
+        }
+        let piece = match self.piece.take() {
+            Some(piece) => piece,
+            None => return,
+        };
+        self.can_hold = false;
+        self.fall = 0.;
+        self.events.push(GameEvent::PieceHeld(piece.piece_type));
+        match self.held_piece.replace(piece.piece_type) {
+            Some(held_piece) => self.spawn_piece(held_piece),
+            None => self.spawn_next_piece(),
+        }
+    }
+
+    fn spawn_next_piece(&mut self) {
+        if let Some(next_piece) = self.generator.next_piece(&mut self.rng) {
+            self.next_pieces.push_back(next_piece);
+        }
+        // the queue is only empty once the pieces of a puzzle are all played
+        match self.next_pieces.pop_front() {
+            Some(piece_type) => self.spawn_piece(piece_type),
+            None => self.end_game(GameEnd::OutOfPieces),
+        }
+    }
+
+    fn spawn_piece(&mut self, piece_type: PieceType) {
+        let piece = Piece::spawn(piece_type, self.config.big, self.board.size());
+        if self.config.top_out == TopOut::ClearBoard && !self.fits(&piece) {
+            // the stack goes away instead of ending the game
+            self.board = Board::new(self.board.size());
+            self.garbage_left = 0;
+            self.events.push(GameEvent::BoardCleared);
+        }
+        self.events.push(GameEvent::PieceSpawned(piece));
+        self.lock_elapsed = Duration::ZERO;
+        self.lock_resets = 0;
+        self.lowest_line = piece.position.y;
+        // the rotation of the previous piece must not make a spin
+        self.last_rotation = None;
+        if self.fits(&piece) {
+            self.piece = Some(piece);
+            self.phase = Phase::Falling;
+        } else {
+            // the board is full
+            self.end_game(GameEnd::ToppedOut);
+        }
+    }
+
+    fn end_game(&mut self, end: GameEnd) {
+        self.phase = Phase::GameOver(end);
+        self.events.push(GameEvent::GameOver(end));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gravity;
+
+    fn game(seed: u64) -> Game {
+        Game::new(GameConfig {
+            seed,
+            gravity: GravityCurve::Off,
+            ..GameConfig::default()
+        })
+    }
+
+    /// Plays a whole game with the same inputs whatever the seed, returning
+    /// everything that happened
+    fn play(seed: u64) -> (Vec<String>, usize, String) {
+        let mut game = Game::new(GameConfig {
+            seed,
+            ..GameConfig::default()
+        });
+        let inputs = [
+            Input::Left,
+            Input::RotateClock,
+            Input::Left,
+            Input::Hold,
+            Input::Right,
+            Input::RotateAnti,
+            Input::Right,
+        ];
+        let mut events = Vec::new();
+        for (i, input) in inputs.iter().cycle().take(600).enumerate() {
+            game.step(*input);
+            if i % 4 == 0 {
+                game.step(Input::HardDrop);
+            }
+            game.tick(Duration::from_millis(50));
+            events.extend(game.drain_events().map(|event| format!("{:?}", event)));
+        }
+        (events, game.score(), game.board.to_string())
+    }
+
+    /// A board filled up to `rows`, but for column `x`
+    fn well(rows: i32, x: i32) -> Board {
+        (1..=rows)
+            .flat_map(|y| {
+                (1..=BoardSize::STANDARD.width)
+                    .filter(move |column| *column != x)
+                    .map(move |column| BoardPosition::new(column, y))
+            })
+            .collect()
+    }
+
+    /// Makes the current piece a standing bar above column `x`
+    fn bar_over(game: &mut Game, x: i32) {
+        let mut piece = Piece {
+            piece_type: PieceType::Bar,
+            orientation: Orientation::Right,
+            position: BoardPosition::new(x, 15),
+            big: false,
+        };
+        piece.position = piece.position + (x - piece.positions()[0].0, 0);
+        game.piece = Some(piece);
+    }
+
+    #[test]
+    fn same_seed_same_game() {
+        let (events, score, board) = play(42);
+        assert!(
+            events
+                .iter()
+                .filter(|event| event.starts_with("PieceLocked"))
+                .count()
+                > 10
+        );
+        assert_eq!(play(42), (events, score, board));
+    }
+
+    #[test]
+    fn seed_picks_the_pieces_and_the_garbage() {
+        let dig = |seed| {
+            Game::new(GameConfig {
+                seed,
+                garbage: Some(Garbage {
+                    rows: 6,
+                    messiness: 1.,
+                    hole_change: 0.5,
+                }),
+                ..GameConfig::default()
+            })
+        };
+        let (game, same, other) = (dig(7), dig(7), dig(8));
+        assert_eq!(game.seed(), 7);
+        assert_eq!(
+            game.piece.unwrap().piece_type,
+            same.piece.unwrap().piece_type
+        );
+        assert_eq!(game.next_pieces(), same.next_pieces());
+        assert_eq!(game.board.to_string(), same.board.to_string());
+        assert_ne!(game.next_pieces(), other.next_pieces());
+        assert_ne!(game.board.to_string(), other.board.to_string());
+        assert_ne!(play(42).0, play(43).0);
+    }
+
+    #[test]
+    fn full_lines_are_cleared() {
+        let mut game = game(1);
+        for x in 1..=6 {
+            game.board.fill((x, 1));
+        }
+        game.board.fill((1, 2));
+        game.piece = Some(Piece {
+            piece_type: PieceType::Bar,
+            orientation: Orientation::Up,
+            position: BoardPosition::new(8, 10),
+            big: false,
+        });
+        game.drain_events().count();
+        game.step(Input::HardDrop);
+        let events: Vec<_> = game.drain_events().collect();
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, GameEvent::LinesCleared(lines) if lines == &[1])));
+        assert_eq!(game.lines(), 1);
+        // what was above the line went down
+        assert!(game.board.is_concrete((1, 1)));
+        assert!(!game.board.is_concrete((1, 2)));
+        assert!(!game.board.is_concrete((2, 1)));
+    }
+
+    #[test]
+    fn bar_kicks_off_the_wall() {
+        let mut game = game(1);
+        game.piece = Some(Piece {
+            piece_type: PieceType::Bar,
+            orientation: Orientation::Left,
+            position: BoardPosition::new(1, 10),
+            big: false,
+        });
+        game.step(Input::RotateClock);
+        // in place the bar would be in the left wall, the second kick moves
+        // it one column right
+        let piece = game.piece.unwrap();
+        assert_eq!(piece.orientation, Orientation::Up);
+        assert_eq!(piece.positions(), [(1, 10), (2, 10), (3, 10), (4, 10)]);
+        assert_eq!(game.last_rotation, Some(1));
+    }
+
+    #[test]
+    fn stacking_in_the_middle_tops_out() {
+        let mut game = game(1);
+        for _ in 0..100 {
+            if game.end().is_some() {
+                break;
+            }
+            game.step(Input::HardDrop);
+            game.tick(Duration::from_millis(50));
+        }
+        assert_eq!(game.end(), Some(GameEnd::ToppedOut));
+        assert_eq!(game.lines(), 0);
+    }
+
+    #[test]
+    fn topping_out_can_clear_the_board() {
+        let mut game = Game::new(GameConfig {
+            seed: 1,
+            top_out: TopOut::ClearBoard,
+            ..GameConfig::default()
+        });
+        // a hole in each row so no line is full
+        game.board = (1..=BoardSize::STANDARD.height)
+            .flat_map(|y| (2..=BoardSize::STANDARD.width).map(move |x| BoardPosition::new(x, y)))
+            .collect();
+        game.piece = None;
+        game.spawn_next_piece();
+        assert_eq!(game.end(), None);
+        assert!(game.board.is_empty());
+        assert!(game
+            .drain_events()
+            .any(|event| matches!(event, GameEvent::BoardCleared)));
+    }
+
+    #[test]
+    fn t_spin_double() {
+        let mut game = game(1);
+        // a T slot under an overhang
+        game.board = (1..=10)
+            .filter(|x| *x != 5)
+            .map(|x| BoardPosition::new(x, 1))
+            .chain(
+                (1..=10)
+                    .filter(|x| !(4..=6).contains(x))
+                    .map(|x| BoardPosition::new(x, 2)),
+            )
+            .chain((1..=4).map(|x| BoardPosition::new(x, 3)))
+            .collect();
+        game.piece = Some(Piece {
+            piece_type: PieceType::T,
+            orientation: Orientation::Right,
+            position: BoardPosition::new(5, 2),
+            big: false,
+        });
+        game.drain_events().count();
+        game.step(Input::RotateClock);
+        game.step(Input::HardDrop);
+        assert!(game
+            .drain_events()
+            .any(|event| matches!(event, GameEvent::Clear(ClearKind::TSpinDouble))));
+        assert_eq!(game.lines(), 2);
+    }
+
+    #[test]
+    fn moves_restart_the_lock_delay_15_times() {
+        let on_ground = |lock_reset| {
+            let mut game = Game::new(GameConfig {
+                seed: 1,
+                lock_reset,
+                ..GameConfig::default()
+            });
+            let mut piece = Piece::spawn(PieceType::Square, false, BoardSize::STANDARD);
+            piece.position = piece.position + (0, -game.drop_distance(&piece));
+            game.piece = Some(piece);
+            game.lowest_line = piece.position.y;
+            game.drain_events().count();
+            game
+        };
+        let locked = |game: &mut Game| {
+            game.drain_events()
+                .any(|event| matches!(event, GameEvent::PieceLocked(_)))
+        };
+        let mut game = on_ground(LockReset::Move);
+        for i in 0..MAX_LOCK_RESETS {
+            game.tick(Duration::from_millis(400));
+            let input = if i % 2 == 0 {
+                Input::Left
+            } else {
+                Input::Right
+            };
+            game.step(input);
+            assert!(!locked(&mut game));
+        }
+        // the last move does not give more time
+        game.tick(Duration::from_millis(400));
+        game.step(Input::Left);
+        game.tick(Duration::from_millis(100));
+        assert!(locked(&mut game));
+
+        for lock_reset in [LockReset::Step, LockReset::Never] {
+            let mut game = on_ground(lock_reset);
+            game.tick(Duration::from_millis(400));
+            game.step(Input::Left);
+            game.tick(Duration::from_millis(100));
+            assert!(locked(&mut game));
+        }
+    }
+
+    #[test]
+    fn high_gravity_drops_several_lines_per_tick() {
+        for gravity in [
+            GravityCurve::Guideline,
+            GravityCurve::Nes,
+            GravityCurve::Tgm,
+        ] {
+            for level in [1, 20, 500, 999, 10_000, usize::MAX] {
+                let lines_per_frame = gravity.gravity(level);
+                assert!(lines_per_frame > 0. && lines_per_frame <= gravity::MAX_GRAVITY);
+            }
+            let mut game = Game::new(GameConfig {
+                seed: 1,
+                gravity,
+                ..GameConfig::default()
+            });
+            game.level = 10_000;
+            let piece = game.piece.unwrap();
+            let distance = game.drop_distance(&piece);
+            game.tick(Duration::from_millis(50));
+            // 1G at least: three frames go down three lines
+            assert!(piece.position.y - game.piece.unwrap().position.y >= distance.min(3));
+            for _ in 0..1000 {
+                // the level goes back with the lines at each lock
+                game.level = 10_000;
+                game.tick(Duration::from_millis(50));
+            }
+            assert_eq!(game.end(), Some(GameEnd::ToppedOut));
+        }
+    }
+
+    #[test]
+    fn previews_show_1_to_6_pieces() {
+        for (next_pieces, shown) in [(0, 1), (3, 3), (10, MAX_NEXT_PIECES)] {
+            let game = Game::new(GameConfig {
+                seed: 1,
+                next_pieces,
+                ..GameConfig::default()
+            });
+            assert_eq!(game.next_pieces().len(), shown);
+        }
+    }
+
+    #[test]
+    fn big_pieces_move_by_blocks_inside_the_walls() {
+        let mut game = Game::new(GameConfig {
+            seed: 1,
+            gravity: GravityCurve::Off,
+            big: true,
+            ..GameConfig::default()
+        });
+        let columns = |game: &Game| {
+            let positions = game.piece.unwrap().positions();
+            let columns = positions.iter().map(|(x, _)| *x);
+            (columns.clone().min().unwrap(), columns.max().unwrap())
+        };
+        game.piece = Some(Piece::spawn(PieceType::Bar, true, BoardSize::STANDARD));
+        game.step(Input::RotateClock);
+        let (left, right) = columns(&game);
+        assert_eq!(right - left, 1);
+        game.step(Input::Left);
+        assert_eq!(columns(&game), (left - 2, right - 2));
+        for _ in 0..10 {
+            game.step(Input::Right);
+        }
+        assert_eq!(columns(&game).1, BoardSize::STANDARD.width);
+        // lying down against the wall, the bar is kicked back in
+        game.step(Input::RotateClock);
+        let piece = game.piece.unwrap();
+        assert_eq!(piece.orientation, Orientation::Bottom);
+        assert_eq!(columns(&game), (3, BoardSize::STANDARD.width));
+        for _ in 0..10 {
+            game.step(Input::Left);
+        }
+        assert_eq!(columns(&game).0, 1);
+    }
+
+    #[test]
+    fn hold_swaps_once_per_piece() {
+        let mut game = game(1);
+        let first = game.piece.unwrap().piece_type;
+        let second = game.next_pieces()[0];
+        game.step(Input::Hold);
+        assert_eq!(game.held_piece, Some(first));
+        assert_eq!(game.piece.unwrap().piece_type, second);
+        // the new piece cannot be held before it is locked
+        game.step(Input::Hold);
+        assert_eq!(game.piece.unwrap().piece_type, second);
+        game.step(Input::HardDrop);
+        let third = game.piece.unwrap().piece_type;
+        game.step(Input::Hold);
+        assert_eq!(game.held_piece, Some(third));
+        let piece = game.piece.unwrap();
+        assert_eq!(piece.piece_type, first);
+        // the held piece comes back at the top
+        assert_eq!(
+            piece.positions(),
+            Piece::spawn(first, false, BoardSize::STANDARD).positions()
+        );
+    }
+    #[test]
+    fn sprint_ends_on_the_line_goal() {
+        let mut game = Game::new(GameConfig {
+            seed: 1,
+            gravity: GravityCurve::Off,
+            line_goal: Some(8),
+            ..GameConfig::default()
+        });
+        for goal_reached in [false, true] {
+            game.board = well(4, 10);
+            bar_over(&mut game, 10);
+            game.step(Input::HardDrop);
+            assert_eq!(game.end().is_some(), goal_reached);
+            game.tick(Duration::from_secs(1));
+        }
+        assert_eq!(game.end(), Some(GameEnd::GoalReached));
+        assert_eq!(game.lines(), 8);
+    }
+    #[test]
+    fn ultra_ends_when_the_time_is_up() {
+        let mut game = Game::new(GameConfig {
+            seed: 1,
+            gravity: GravityCurve::Off,
+            time_limit: Some(Duration::from_secs(2)),
+            ..GameConfig::default()
+        });
+        game.tick(Duration::from_secs(1));
+        assert_eq!(game.end(), None);
+        assert_eq!(game.remaining(), Some(Duration::from_secs(1)));
+        game.tick(Duration::from_millis(1500));
+        assert_eq!(game.end(), Some(GameEnd::TimeUp));
+        assert_eq!(game.remaining(), Some(Duration::ZERO));
+        assert_eq!(game.elapsed(), Duration::from_secs(2));
+        // the clock stops with the game
+        game.tick(Duration::from_secs(1));
+        assert_eq!(game.elapsed(), Duration::from_secs(2));
+    }
+    #[test]
+    fn clearing_the_garbage_ends_the_game() {
+        let mut game = Game::new(GameConfig {
+            seed: 1,
+            gravity: GravityCurve::Off,
+            // a single well through all the rows
+            garbage: Some(Garbage {
+                rows: 5,
+                messiness: 0.,
+                hole_change: 0.,
+            }),
+            ..GameConfig::default()
+        });
+        assert_eq!(game.garbage_left, 5);
+        let hole = (1..=BoardSize::STANDARD.width)
+            .find(|x| !game.board.is_concrete((*x, 1)))
+            .unwrap();
+        bar_over(&mut game, hole);
+        game.step(Input::HardDrop);
+        assert_eq!(game.garbage_left, 1);
+        assert_eq!(game.end(), None);
+        game.tick(Duration::from_secs(1));
+        bar_over(&mut game, hole);
+        game.step(Input::HardDrop);
+        assert_eq!(game.garbage_left, 0);
+        assert_eq!(game.end(), Some(GameEnd::GoalReached));
+    }
+    #[test]
+    fn sections_stop_at_99_until_a_line_is_cleared() {
+        let mut game = Game::new(GameConfig {
+            seed: 1,
+            gravity: GravityCurve::Off,
+            start_level: 98,
+            level_progression: LevelProgression::Sections,
+            ..GameConfig::default()
+        });
+        let first_grade = game.grade().unwrap();
+        assert_eq!(first_grade.to_string(), "9");
+        for _ in 0..2 {
+            game.step(Input::HardDrop);
+            assert_eq!(game.level(), 99);
+            game.tick(Duration::from_secs(1));
+        }
+        game.board = well(1, 10);
+        bar_over(&mut game, 10);
+        game.step(Input::HardDrop);
+        assert_eq!(game.level(), 100);
+        assert!(game.grade().unwrap() > first_grade);
+        // only games played by sections are graded
+        assert_eq!(self::game(1).grade(), None);
+    }
+}
@@ -3,41 +3,183 @@ use bevy::{
     sprite::MaterialMesh2dBundle,
 };
 
-use crate::{WINDOW_HEIGHT, WINDOW_WIDTH};
+use crate::{
+    engine::{Game, GameEnd},
+    high_score::format_duration,
+    window_size,
+};
+
+/// Marker component: everything displayed when the game is over
+#[derive(Component)]
+pub struct GameOverScreen;
+
 pub fn game_over(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     asset_server: Res<AssetServer>,
+    game: Res<Game>,
+) {
+    // a puzzle is failed by topping out or by playing all its pieces
+    let (title, result) = match game.puzzle() {
+        Some(_) if game.end() == Some(GameEnd::OutOfPieces) => {
+            ("FAILED", Some("NO MORE PIECES".to_string()))
+        }
+        Some(_) => ("FAILED", None),
+        None => ("GAME\nOVER", None),
+    };
+    spawn_end_screen(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        &asset_server,
+        &game,
+        title,
+        Color::RED,
+        result,
+    );
+}
+
+/// The goal of the game has been reached
+pub fn won(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    game: Res<Game>,
+) {
+    let title = match game.puzzle() {
+        Some(_) => "SOLVED",
+        None => "CLEAR",
+    };
+    spawn_end_screen(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        &asset_server,
+        &game,
+        title,
+        Color::GOLD,
+        Some(format!("TIME {}", format_duration(game.elapsed()))),
+    );
+}
+
+/// The time limit of the game has expired
+pub fn time_up(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    game: Res<Game>,
+) {
+    spawn_end_screen(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        &asset_server,
+        &game,
+        "TIME\nUP",
+        Color::GOLD,
+        Some(format!("SCORE {}", game.score())),
+    );
+}
+
+/// Spawns the title of the end of the game, followed by its result when
+/// there is one to show
+#[allow(clippy::too_many_arguments)]
+fn spawn_end_screen(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    asset_server: &AssetServer,
+    game: &Game,
+    title: &str,
+    color: Color,
+    result: Option<String>,
 ) {
     let font = asset_server.load("FiraCode-Regular.ttf");
     let text_style = TextStyle {
         font,
         font_size: 120.0,
-        color: Color::RED,
+        color,
     };
     let text_alignment = TextAlignment {
         vertical: VerticalAlign::Center,
         horizontal: HorizontalAlign::Center,
     };
-    commands.spawn_bundle(Text2dBundle {
-        text: Text::from_section("GAME\nOVER", text_style.clone()).with_alignment(text_alignment),
-        transform: Transform::from_translation(Vec3::new(0., 0., 10.)),
-        ..default()
-    });
-
-    commands.spawn_bundle(MaterialMesh2dBundle {
-        mesh: meshes
-            .add(
-                Quad {
-                    size: Vec2::new(WINDOW_WIDTH, WINDOW_HEIGHT),
-                    ..Default::default()
-                }
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(title, text_style.clone()).with_alignment(text_alignment),
+            transform: Transform::from_translation(Vec3::new(0., 0., 10.)),
+            ..default()
+        })
+        .insert(GameOverScreen);
+
+    if let Some(result) = result {
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::from_section(
+                    result,
+                    TextStyle {
+                        font_size: 40.0,
+                        color: Color::ANTIQUE_WHITE,
+                        ..text_style.clone()
+                    },
+                )
+                .with_alignment(text_alignment),
+                transform: Transform::from_translation(Vec3::new(0., -100., 10.)),
+                ..default()
+            })
+            .insert(GameOverScreen);
+    }
+
+    // the seed allows to play the same game again
+    let small_text_style = TextStyle {
+        font_size: 20.0,
+        color: Color::ANTIQUE_WHITE,
+        ..text_style
+    };
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(format!("seed {}", game.seed()), small_text_style.clone())
+                .with_alignment(text_alignment),
+            transform: Transform::from_translation(Vec3::new(0., -150., 10.)),
+            ..default()
+        })
+        .insert(GameOverScreen);
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section("R: retry\nENTER: main menu", small_text_style)
+                .with_alignment(text_alignment),
+            transform: Transform::from_translation(Vec3::new(0., -190., 10.)),
+            ..default()
+        })
+        .insert(GameOverScreen);
+
+    commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            mesh: meshes
+                .add(
+                    Quad {
+                        size: window_size(game.board_size()),
+                        ..Default::default()
+                    }
+                    .into(),
+                )
                 .into(),
-            )
-            .into(),
-        material: materials.add(ColorMaterial::from(Color::rgba(0., 0., 0., 0.5))),
-        transform: Transform::from_translation(Vec3::new(0., 0., 5.)),
-        ..default()
-    });
+            material: materials.add(ColorMaterial::from(Color::rgba(0., 0., 0., 0.5))),
+            transform: Transform::from_translation(Vec3::new(0., 0., 5.)),
+            ..default()
+        })
+        .insert(GameOverScreen);
+}
+
+pub fn exit_game_over(
+    mut commands: Commands,
+    game_over_elements: Query<Entity, With<GameOverScreen>>,
+) {
+    for e in &game_over_elements {
+        commands.entity(e).despawn();
+    }
 }
@@ -0,0 +1,163 @@
+//! Rows of garbage to dig through.
+
+use bevy::prelude::*;
+use rand::{seq::SliceRandom, Rng, RngCore};
+
+use crate::{
+    board::{BoardPosition, BoardSize},
+    engine::GameEvent,
+    square::{spawn_square, Square},
+};
+
+const GARBAGE_COLOR: Color = Color::GRAY;
+
+/// How the rows of garbage at the bottom of the board are generated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Garbage {
+    pub rows: usize,
+    /// How far the hole of a row can be from the hole of the row below when
+    /// it changes: from 0, a column next to it, to 1, any column
+    pub messiness: f64,
+    /// Probability for the hole of a row to be in another column than the
+    /// hole of the row below: from 0, a single well, to 1, a new hole on each
+    /// row
+    pub hole_change: f64,
+}
+
+impl Garbage {
+    /// The number of garbage rows on a board of the given size: never more
+    /// than half of its height, to leave room for the pieces
+    pub fn rows_on(&self, board_size: BoardSize) -> usize {
+        self.rows.min(board_size.height as usize / 2)
+    }
+
+    /// The squares of the garbage rows of a board of the given size, each row
+    /// having a single hole
+    pub fn generate(&self, rng: &mut dyn RngCore, board_size: BoardSize) -> Vec<BoardPosition> {
+        let width = board_size.width;
+        let mut positions = Vec::new();
+        let mut hole = rng.gen_range(1..=width);
+        for y in 1..=self.rows_on(board_size) as i32 {
+            if y > 1 && rng.gen_bool(self.hole_change.clamp(0., 1.)) {
+                let reach = 1 + (self.messiness.clamp(0., 1.) * (width - 2) as f64).round() as i32;
+                // never the current column, the board has at least 4
+                let columns: Vec<i32> = (1..=width)
+                    .filter(|x| *x != hole && (x - hole).abs() <= reach)
+                    .collect();
+                hole = *columns.choose(rng).unwrap();
+            }
+            positions.extend(
+                (1..=width)
+                    .filter(|x| *x != hole)
+                    .map(|x| BoardPosition::new(x, y)),
+            );
+        }
+        positions
+    }
+}
+
+/// Spawns the squares of the garbage put on the board
+pub fn render_garbage(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut event_reader: EventReader<GameEvent>,
+) {
+    for event in event_reader.iter() {
+        if let GameEvent::GarbageAdded(positions) = event {
+            for position in positions {
+                spawn_square(
+                    &mut commands,
+                    &mut meshes,
+                    &mut materials,
+                    *position,
+                    GARBAGE_COLOR,
+                    Square,
+                    None::<Square>,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    #[test]
+    fn rows_fit_the_board() {
+        let garbage = Garbage {
+            rows: 10,
+            messiness: 1.,
+            hole_change: 0.7,
+        };
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        for (board_size, rows) in [
+            (BoardSize::STANDARD, 10),
+            (BoardSize::MIN, 4),
+            (BoardSize::new(20, 12), 6),
+        ] {
+            assert_eq!(garbage.rows_on(board_size), rows);
+            let positions = garbage.generate(&mut rng, board_size);
+            // a single hole in each row
+            for y in 1..=rows as i32 {
+                let squares = positions.iter().filter(|bp| bp.y == y).count();
+                assert_eq!(squares, board_size.width as usize - 1);
+            }
+            assert_eq!(positions.len(), rows * (board_size.width as usize - 1));
+        }
+    }
+
+    /// The column of the hole of each row, from the bottom
+    fn holes(garbage: Garbage, seed: u64) -> Vec<i32> {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let positions = garbage.generate(&mut rng, BoardSize::STANDARD);
+        (1..=garbage.rows as i32)
+            .map(|y| {
+                (1..=BoardSize::STANDARD.width)
+                    .find(|x| !positions.contains(&BoardPosition::new(*x, y)))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn holes_never_change_makes_a_well() {
+        let garbage = Garbage {
+            rows: 10,
+            messiness: 1.,
+            hole_change: 0.,
+        };
+        for seed in 0..10 {
+            let holes = holes(garbage, seed);
+            assert!(holes.iter().all(|hole| *hole == holes[0]));
+        }
+    }
+
+    #[test]
+    fn messiness_is_how_far_holes_move() {
+        let tidy = Garbage {
+            rows: 10,
+            messiness: 0.,
+            hole_change: 1.,
+        };
+        let messy = Garbage {
+            messiness: 1.,
+            ..tidy
+        };
+        let mut far_moves = 0;
+        for seed in 0..10 {
+            for pair in holes(tidy, seed).windows(2) {
+                assert_eq!((pair[0] - pair[1]).abs(), 1);
+            }
+            for pair in holes(messy, seed).windows(2) {
+                assert_ne!(pair[0], pair[1]);
+                far_moves += usize::from((pair[0] - pair[1]).abs() > 1);
+            }
+        }
+        assert!(far_moves > 0);
+    }
+}
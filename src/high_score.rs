@@ -0,0 +1,668 @@
+//! The best games of each mode, kept between runs.
+//!
+//! They are saved in a text file of the user's data directory: a header
+//! giving the version of the format, then one game per line with its fields
+//! separated by tabs.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{
+    prelude::{shape::Quad, *},
+    sprite::MaterialMesh2dBundle,
+};
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::{
+    board::BoardSize,
+    end_state,
+    engine::{Game, GameEnd},
+    menu::GameSetup,
+    mode::{GameMode, Ranking},
+    player::{Action, Player},
+    window_size, GameState,
+};
+
+/// Number of games kept for each mode
+const MAX_HIGH_SCORES: usize = 10;
+/// First line of the file, the version must be increased when the format
+/// changes
+const FILE_HEADER: &str = "tetris-rs high scores v1";
+const NAME_LENGTH: usize = 3;
+/// Characters a name can be made of, in the order they are cycled through
+const NAME_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789 ";
+
+#[derive(Clone, Debug)]
+pub struct HighScore {
+    pub name: String,
+    pub score: usize,
+    pub lines: usize,
+    pub level: usize,
+    pub duration: Duration,
+    /// Seconds since the Unix epoch
+    pub date: u64,
+    pub seed: u64,
+}
+
+impl HighScore {
+    /// The result of a finished game, before the player gives a name,
+    /// `None` when it cannot enter the high scores
+    pub fn from_game(game: &Game, ranking: Ranking) -> Option<Self> {
+        // the tables compare games played on the same board
+        if game.board_size() != BoardSize::STANDARD {
+            return None;
+        }
+        let worth_it = match ranking {
+            // an empty game is not worth a name
+            Ranking::Score => game.score() > 0,
+            Ranking::Time => game.end() == Some(GameEnd::GoalReached),
+            Ranking::Unranked => false,
+        };
+        if !worth_it {
+            return None;
+        }
+        Some(Self {
+            name: String::new(),
+            score: game.score(),
+            lines: game.lines(),
+            level: game.level(),
+            duration: game.elapsed(),
+            date: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_secs()),
+            seed: game.seed(),
+        })
+    }
+
+    /// Is this game better than the other one?
+    fn ranks_before(&self, other: &HighScore, ranking: Ranking) -> bool {
+        match ranking {
+            // the fastest game wins a tie
+            Ranking::Score => {
+                self.score > other.score
+                    || (self.score == other.score && self.duration < other.duration)
+            }
+            Ranking::Time => self.duration < other.duration,
+            Ranking::Unranked => false,
+        }
+    }
+}
+
+/// The best games of each mode, best first
+#[derive(Default)]
+pub struct HighScores {
+    tables: HashMap<GameMode, Vec<HighScore>>,
+}
+
+impl HighScores {
+    /// Reads the saved high scores, a missing or unreadable file gives
+    /// empty tables
+    pub fn load() -> Self {
+        let path = match file_path() {
+            Some(path) => path,
+            None => return Self::default(),
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => Self::parse(&content).unwrap_or_else(|| {
+                eprintln!("Unknown high score file format: {}", path.display());
+                Self::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                eprintln!("Cannot read high scores from {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = file_path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.file_content())
+    }
+
+    pub fn table(&self, mode: GameMode) -> &[HighScore] {
+        self.tables.get(&mode).map_or(&[], |table| table.as_slice())
+    }
+
+    /// Position the game would take in the table of its mode, `None` when
+    /// it is not good enough
+    pub fn rank(&self, mode: GameMode, high_score: &HighScore) -> Option<usize> {
+        let ranking = mode.ranking();
+        // in case of a tie the oldest game stays first
+        let rank = self
+            .table(mode)
+            .iter()
+            .take_while(|other| !high_score.ranks_before(other, ranking))
+            .count();
+        (rank < MAX_HIGH_SCORES).then_some(rank)
+    }
+
+    pub fn insert(&mut self, mode: GameMode, high_score: HighScore) {
+        if let Some(rank) = self.rank(mode, &high_score) {
+            let table = self.tables.entry(mode).or_default();
+            table.insert(rank, high_score);
+            table.truncate(MAX_HIGH_SCORES);
+        }
+    }
+
+    fn parse(content: &str) -> Option<Self> {
+        let mut lines = content.lines();
+        if lines.next()? != FILE_HEADER {
+            return None;
+        }
+        let mut high_scores = Self::default();
+        for line in lines {
+            // a damaged line does not lose the other ones
+            match parse_line(line) {
+                Some((mode, high_score)) => high_scores.insert(mode, high_score),
+                None => eprintln!("Ignoring high score line: {:?}", line),
+            }
+        }
+        Some(high_scores)
+    }
+
+    fn file_content(&self) -> String {
+        let mut content = format!("{}\n", FILE_HEADER);
+        for mode in GameMode::ALL {
+            for high_score in self.table(mode) {
+                content.push_str(&format!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                    mode.id(),
+                    high_score.name,
+                    high_score.score,
+                    high_score.lines,
+                    high_score.level,
+                    high_score.duration.as_millis(),
+                    high_score.date,
+                    high_score.seed,
+                ));
+            }
+        }
+        content
+    }
+}
+
+fn parse_line(line: &str) -> Option<(GameMode, HighScore)> {
+    let mut fields = line.split('\t');
+    let mode = GameMode::from_id(fields.next()?)?;
+    let high_score = HighScore {
+        name: fields.next()?.to_string(),
+        score: fields.next()?.parse().ok()?,
+        lines: fields.next()?.parse().ok()?,
+        level: fields.next()?.parse().ok()?,
+        duration: Duration::from_millis(fields.next()?.parse().ok()?),
+        date: fields.next()?.parse().ok()?,
+        seed: fields.next()?.parse().ok()?,
+    };
+    Some((mode, high_score))
+}
+
+fn file_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("tetris-rs").join("high_scores.txt"))
+}
+
+/// Formats a duration as `minutes:seconds.milliseconds`
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    format!(
+        "{}:{:02}.{:03}",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Formats seconds since the Unix epoch as a `year-month-day` UTC date
+fn format_date(date: u64) -> String {
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (date / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // months starting in March
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Name being entered for a new high score, kept for the next one
+pub struct NameEntry {
+    /// indexes in [`NAME_CHARS`]
+    name: [usize; NAME_LENGTH],
+    cursor: usize,
+}
+
+impl Default for NameEntry {
+    fn default() -> Self {
+        Self {
+            name: [0; NAME_LENGTH],
+            cursor: 0,
+        }
+    }
+}
+
+impl NameEntry {
+    fn name(&self) -> String {
+        self.name
+            .iter()
+            .map(|index| NAME_CHARS[*index] as char)
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    /// The name with the character being edited between brackets
+    fn display(&self) -> String {
+        self.name
+            .iter()
+            .enumerate()
+            .map(|(i, index)| {
+                let c = NAME_CHARS[*index] as char;
+                if i == self.cursor {
+                    format!("[{}]", c)
+                } else {
+                    format!(" {} ", c)
+                }
+            })
+            .collect()
+    }
+}
+
+/// Marker component: everything displayed by the name entry and the high
+/// score table
+#[derive(Component)]
+pub struct HighScoreScreen;
+
+#[derive(Component)]
+pub struct NameText;
+
+/// The table of high scores shown, browsing the tables does not change the
+/// mode of the next game
+#[derive(Component)]
+pub struct HighScoreTable {
+    mode: GameMode,
+}
+
+/// Spawns a text of the high score screens
+fn spawn_text(commands: &mut Commands, text_style: TextStyle, value: String, y: f32) -> Entity {
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(value, text_style).with_alignment(TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Center,
+            }),
+            transform: Transform::from_translation(Vec3::new(0., y, 10.)),
+            ..default()
+        })
+        .insert(HighScoreScreen)
+        .id()
+}
+
+/// Covers the window displaying a board of the given size
+fn spawn_background(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    board_size: BoardSize,
+    color: Color,
+) {
+    commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            mesh: meshes
+                .add(
+                    Quad {
+                        size: window_size(board_size),
+                        ..Default::default()
+                    }
+                    .into(),
+                )
+                .into(),
+            material: materials.add(ColorMaterial::from(color)),
+            transform: Transform::from_translation(Vec3::new(0., 0., 5.)),
+            ..default()
+        })
+        .insert(HighScoreScreen);
+}
+
+pub fn exit_high_score_screen(
+    mut commands: Commands,
+    screen_elements: Query<Entity, With<HighScoreScreen>>,
+) {
+    for e in &screen_elements {
+        commands.entity(e).despawn();
+    }
+}
+
+pub fn enter_name_entry(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    game: Res<Game>,
+    name_entry: Res<NameEntry>,
+) {
+    let text_style = TextStyle {
+        font: asset_server.load("FiraCode-Regular.ttf"),
+        font_size: 60.0,
+        color: Color::GOLD,
+    };
+    spawn_text(
+        &mut commands,
+        text_style.clone(),
+        "NEW\nHIGH SCORE".to_string(),
+        150.,
+    );
+    spawn_text(
+        &mut commands,
+        TextStyle {
+            font_size: 30.0,
+            color: Color::ANTIQUE_WHITE,
+            ..text_style.clone()
+        },
+        format!("SCORE {}", game.score()),
+        50.,
+    );
+    let name_text = spawn_text(
+        &mut commands,
+        TextStyle {
+            color: Color::ANTIQUE_WHITE,
+            ..text_style.clone()
+        },
+        name_entry.display(),
+        -30.,
+    );
+    commands.entity(name_text).insert(NameText);
+    spawn_text(
+        &mut commands,
+        TextStyle {
+            font_size: 20.0,
+            color: Color::ANTIQUE_WHITE,
+            ..text_style
+        },
+        "UP/DOWN: letter\nENTER: save".to_string(),
+        -120.,
+    );
+    spawn_background(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        game.board_size(),
+        Color::rgba(0., 0., 0., 0.5),
+    );
+}
+
+/// Edits the name with the arrows, saves the high score with select
+pub fn name_entry(
+    mut input_query: Query<&mut ActionState<Action>, With<Player>>,
+    mut name_entry: ResMut<NameEntry>,
+    mut high_scores: ResMut<HighScores>,
+    game: Res<Game>,
+    setup: Res<GameSetup>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let mut input = input_query.single_mut();
+    if input.just_pressed(Action::Left) {
+        name_entry.cursor = name_entry.cursor.saturating_sub(1);
+    }
+    if input.just_pressed(Action::Right) {
+        name_entry.cursor = (name_entry.cursor + 1).min(NAME_LENGTH - 1);
+    }
+    let cursor = name_entry.cursor;
+    if input.just_pressed(Action::Up) {
+        name_entry.name[cursor] = (name_entry.name[cursor] + 1) % NAME_CHARS.len();
+    }
+    if input.just_pressed(Action::Down) {
+        name_entry.name[cursor] =
+            (name_entry.name[cursor] + NAME_CHARS.len() - 1) % NAME_CHARS.len();
+    }
+    if input.just_pressed(Action::Select) {
+        // the end screen is updated within the same frame, it must not see
+        // select again
+        input.consume(Action::Select);
+        // the name entry is only shown for games worth a high score
+        if let Some(high_score) = HighScore::from_game(&game, setup.ranking()) {
+            high_scores.insert(
+                setup.mode,
+                HighScore {
+                    name: name_entry.name(),
+                    ..high_score
+                },
+            );
+            if let Err(e) = high_scores.save() {
+                eprintln!("Cannot save high scores: {}", e);
+            }
+        }
+        let _ = state.set(end_state(&game));
+    }
+}
+
+pub fn render_name_entry(name_entry: Res<NameEntry>, mut query: Query<&mut Text, With<NameText>>) {
+    if name_entry.is_changed() {
+        for mut text in &mut query {
+            text.sections[0].value = name_entry.display();
+        }
+    }
+}
+
+pub fn enter_high_scores(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    high_scores: Res<HighScores>,
+    setup: Res<GameSetup>,
+    board_size: Res<BoardSize>,
+) {
+    let text_style = TextStyle {
+        font: asset_server.load("FiraCode-Regular.ttf"),
+        font_size: 14.0,
+        color: Color::ANTIQUE_WHITE,
+    };
+    let mode = ranked_mode(setup.mode, 1);
+    let table = spawn_text(
+        &mut commands,
+        text_style.clone(),
+        table_text(mode, &high_scores),
+        60.,
+    );
+    commands.entity(table).insert(HighScoreTable { mode });
+    spawn_text(
+        &mut commands,
+        TextStyle {
+            font_size: 20.0,
+            ..text_style
+        },
+        "LEFT/RIGHT: mode\nENTER: back".to_string(),
+        -200.,
+    );
+    spawn_background(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        *board_size,
+        Color::BLACK,
+    );
+}
+
+/// Shows the table of another mode with left & right, goes back to the
+/// main menu with select
+pub fn high_scores(
+    mut input_query: Query<&mut ActionState<Action>, With<Player>>,
+    mut table_query: Query<&mut HighScoreTable>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let mut input = input_query.single_mut();
+    // the table is spawned by the commands of the frame the screen is entered
+    let mut table = match table_query.get_single_mut() {
+        Ok(table) => table,
+        Err(_) => return,
+    };
+    if input.just_pressed(Action::Left) {
+        table.mode = ranked_mode(table.mode.cycle(-1), -1);
+    }
+    if input.just_pressed(Action::Right) {
+        table.mode = ranked_mode(table.mode.cycle(1), 1);
+    }
+    if input.just_pressed(Action::Select) {
+        // the main menu is updated within the same frame, it must not see
+        // select again
+        input.consume(Action::Select);
+        let _ = state.set(GameState::MainMenu);
+    }
+}
+
+pub fn render_high_scores(
+    high_scores: Res<HighScores>,
+    mut query: Query<(&HighScoreTable, &mut Text), Changed<HighScoreTable>>,
+) {
+    for (table, mut text) in &mut query {
+        text.sections[0].value = table_text(table.mode, &high_scores);
+    }
+}
+
+/// The first mode having a table from `mode`, going `step` by `step`
+fn ranked_mode(mut mode: GameMode, step: isize) -> GameMode {
+    while mode.ranking() == Ranking::Unranked {
+        mode = mode.cycle(step);
+    }
+    mode
+}
+
+fn table_text(mode: GameMode, high_scores: &HighScores) -> String {
+    let title = match mode.ranking() {
+        Ranking::Score => mode.to_string(),
+        Ranking::Time => format!("{} - BEST TIMES", mode),
+        Ranking::Unranked => format!("{} - NOT RANKED", mode),
+    };
+    let mut text = format!(
+        "{}\n\n{:>2} {:<4} {:>7} {:>5} {:>3} {:>9} {:>10} {}\n",
+        title, "#", "NAME", "SCORE", "LINES", "LV", "TIME", "DATE", "SEED"
+    );
+    let table = high_scores.table(mode);
+    if table.is_empty() {
+        text.push_str("\nno game yet\n");
+    }
+    for (rank, high_score) in table.iter().enumerate() {
+        text.push_str(&format!(
+            "{:>2} {:<4} {:>7} {:>5} {:>3} {:>9} {:>10} {}\n",
+            rank + 1,
+            high_score.name,
+            high_score.score,
+            high_score.lines,
+            high_score.level,
+            format_duration(high_score.duration),
+            format_date(high_score.date),
+            high_score.seed,
+        ));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn high_score(score: usize, seconds: u64) -> HighScore {
+        HighScore {
+            name: "ABC".to_string(),
+            score,
+            lines: 10,
+            level: 2,
+            duration: Duration::from_secs(seconds),
+            date: 1_600_000_000,
+            seed: 42,
+        }
+    }
+
+    #[test]
+    fn saved_high_scores_load_back() {
+        let mut high_scores = HighScores::default();
+        high_scores.insert(GameMode::Marathon, high_score(1000, 60));
+        high_scores.insert(GameMode::Marathon, high_score(2000, 90));
+        high_scores.insert(GameMode::Sprint, high_score(0, 45));
+        let content = high_scores.file_content();
+        let loaded = HighScores::parse(&content).unwrap();
+        assert_eq!(loaded.file_content(), content);
+        assert_eq!(loaded.table(GameMode::Marathon)[0].score, 2000);
+        assert_eq!(
+            loaded.table(GameMode::Sprint)[0].duration,
+            Duration::from_secs(45)
+        );
+        assert!(loaded.table(GameMode::Sega).is_empty());
+    }
+
+    #[test]
+    fn unknown_files_and_damaged_lines() {
+        assert!(HighScores::parse("some other file\n").is_none());
+        let content = format!(
+            "{}\nmarathon\tABC\tlots\t1\t1\t1000\t0\t0\nnope\tABC\t1\t1\t1\t1000\t0\t0\n\
+             marathon\tABC\t300\t1\t1\t1000\t0\t0\nmarathon\tABC\t200\n",
+            FILE_HEADER
+        );
+        let high_scores = HighScores::parse(&content).unwrap();
+        let table = high_scores.table(GameMode::Marathon);
+        assert_eq!(table.len(), 1);
+        assert_eq!(table[0].score, 300);
+    }
+
+    #[test]
+    fn rank_by_score() {
+        let mut high_scores = HighScores::default();
+        high_scores.insert(GameMode::Marathon, high_score(1000, 60));
+        high_scores.insert(GameMode::Marathon, high_score(500, 60));
+        let rank = |high_score| high_scores.rank(GameMode::Marathon, &high_score);
+        assert_eq!(rank(high_score(2000, 60)), Some(0));
+        assert_eq!(rank(high_score(700, 60)), Some(1));
+        // a tie goes to the fastest game, then to the oldest one
+        assert_eq!(rank(high_score(500, 30)), Some(1));
+        assert_eq!(rank(high_score(500, 60)), Some(2));
+        assert_eq!(rank(high_score(500, 90)), Some(2));
+    }
+
+    #[test]
+    fn rank_by_time() {
+        let mut high_scores = HighScores::default();
+        high_scores.insert(GameMode::Sprint, high_score(0, 60));
+        high_scores.insert(GameMode::Sprint, high_score(0, 90));
+        let rank = |high_score| high_scores.rank(GameMode::Sprint, &high_score);
+        assert_eq!(rank(high_score(0, 30)), Some(0));
+        assert_eq!(rank(high_score(0, 75)), Some(1));
+        assert_eq!(rank(high_score(0, 120)), Some(2));
+    }
+
+    #[test]
+    fn durations_and_dates() {
+        assert_eq!(format_duration(Duration::from_millis(83_456)), "1:23.456");
+        assert_eq!(format_duration(Duration::from_secs(600)), "10:00.000");
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(1_600_000_000), "2020-09-13");
+    }
+
+    #[test]
+    fn full_tables_keep_the_best() {
+        let mut high_scores = HighScores::default();
+        for score in 1..=MAX_HIGH_SCORES + 2 {
+            high_scores.insert(GameMode::Marathon, high_score(score * 100, 60));
+        }
+        let table = high_scores.table(GameMode::Marathon);
+        assert_eq!(table.len(), MAX_HIGH_SCORES);
+        assert_eq!(table[0].score, (MAX_HIGH_SCORES + 2) * 100);
+        assert_eq!(table[MAX_HIGH_SCORES - 1].score, 300);
+        assert_eq!(
+            high_scores.rank(GameMode::Marathon, &high_score(100, 60)),
+            None
+        );
+    }
+}
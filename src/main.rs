@@ -1,44 +1,74 @@
 #![feature(duration_consts_float)]
 use std::time::Duration;
 
-use bevy::{prelude::*, window::PresentMode};
-use board::{Board, BoardPosition, BOARD_HEIGHT, BOARD_WIDTH};
+use bevy::{prelude::*, transform::TransformSystem, window::PresentMode};
+use board::{BoardPosition, BoardSize};
+use engine::{Game, GameConfig, GameEnd, GameEvent, Input};
+use high_score::{HighScore, HighScores, NameEntry};
 use leafwing_input_manager::prelude::{ActionState, InputManagerPlugin};
-use piece::{spawn_next_piece, Piece, PieceSquare, Rotation};
+use menu::GameSetup;
+use piece::{
+    render_ghost_piece, render_hold_piece, render_next_piece, render_piece, GhostSquare,
+    HoldPieceSquare, NextPieceSquare,
+};
 use player::{spawn_player, Action, Player};
+use puzzle::Puzzles;
+use randomizer::Randomizer;
 use score::{
-    dispayable_changed, increase_score_and_level, setup_score, Level, LineCompleted,
-    LinesCompletedEvent, Score,
+    announce_clear, dispayable_changed, place_hud, reset_score, setup_score, update_play_time,
+    update_score_and_level, BackToBack, Combo, Goal, Level, LineCompleted, MasterGrade, PlayTime,
+    Score,
 };
+use settings::Settings;
 use square::{
-    disappearing_square, spawn_square, to_move_below, MoveBelowEvent, Square, Wall, SQ_TOTAL_SIZE,
+    disappearing_square, place_on_board, spawn_square, to_move_below, DisappearingSquare,
+    MoveBelowEvent, Square, ToMoveBelow, Wall, SQ_TOTAL_SIZE,
 };
 
-use crate::square::{DisappearingSquare, ToMoveBelow};
-
 const FIRST_REPEAT_DELAY: Duration = Duration::from_secs_f32(0.25);
 const KEY_REPEAT_DELAY: Duration = Duration::from_secs_f32(0.1);
-const FAST_DOWN_DELAY: Duration = Duration::from_secs_f64(0.03);
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum GameState {
+    MainMenu,
+    HighScores,
+    /// A high score has been made, its name is being entered
+    NameEntry,
     GameOver,
+    /// The goal of the game has been reached
+    Won,
+    /// The time limit of the game has expired
+    TimeUp,
     InGame,
     Pause,
 }
 
-#[derive(Default)]
-pub struct SpawnPieceEvent;
-
-const WINDOW_WIDTH: f32 = (BOARD_WIDTH + 12) as f32 * SQ_TOTAL_SIZE;
-const WINDOW_HEIGHT: f32 = (BOARD_HEIGHT + 2) as f32 * SQ_TOTAL_SIZE;
+/// Size of the window displaying a board of the given size, never smaller
+/// than for the standard board so that the menus fit in
+pub fn window_size(board_size: BoardSize) -> Vec2 {
+    let standard = BoardSize::STANDARD;
+    Vec2::new(
+        (board_size.width.max(standard.width) + 19) as f32 * SQ_TOTAL_SIZE,
+        (board_size.height.max(standard.height) + 2) as f32 * SQ_TOTAL_SIZE,
+    )
+}
 
 fn main() {
+    let board_size = board_size_from_args();
+    let game_setup = GameSetup {
+        randomizer: randomizer_from_args(),
+        seed: seed_from_args(),
+        board_size: board_size.unwrap_or_default(),
+        custom_board_size: board_size,
+        ..default()
+    };
+    let window = window_size(BoardSize::default());
+
     App::new()
         .insert_resource(WindowDescriptor {
             title: "Oxidized Tetris".to_string(),
-            width: WINDOW_WIDTH,
-            height: WINDOW_HEIGHT,
+            width: window.x,
+            height: window.y,
             present_mode: PresentMode::AutoVsync,
             ..default()
         })
@@ -46,160 +76,336 @@ fn main() {
         // This plugin maps inputs to an input-type agnostic action-state
         // We need to provide it with an enum which stores the possible actions a player could take
         .add_plugin(InputManagerPlugin::<Action>::default())
-        .add_state(GameState::InGame)
+        .add_state(GameState::MainMenu)
         .add_startup_system(setup.chain(setup_score))
-        .add_event::<PieceHasStoppedEvent>()
-        .add_event::<SpawnPieceEvent>()
+        .add_event::<GameEvent>()
         .add_event::<MoveBelowEvent>()
-        .add_event::<LinesCompletedEvent>()
         .add_system(bevy::window::close_on_esc)
         .add_system(pause::pause)
+        .add_system(resize_window)
+        .add_system(square::hide_revealed_stack)
+        // everything is placed on screen once moved on the board
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            place_on_board.before(TransformSystem::TransformPropagate),
+        )
+        .add_system_to_stage(CoreStage::PostUpdate, place_hud.before(place_on_board))
+        .add_system_set(SystemSet::on_enter(GameState::MainMenu).with_system(menu::enter_main_menu))
+        .add_system_set(
+            SystemSet::on_update(GameState::MainMenu)
+                .with_system(menu::main_menu)
+                .with_system(menu::render_main_menu.after(menu::main_menu)),
+        )
+        .add_system_set(SystemSet::on_exit(GameState::MainMenu).with_system(menu::exit_main_menu))
+        .add_system_set(
+            SystemSet::on_enter(GameState::HighScores).with_system(high_score::enter_high_scores),
+        )
+        .add_system_set(
+            SystemSet::on_update(GameState::HighScores)
+                .with_system(high_score::high_scores)
+                .with_system(high_score::render_high_scores.after(high_score::high_scores)),
+        )
+        .add_system_set(
+            SystemSet::on_exit(GameState::HighScores)
+                .with_system(high_score::exit_high_score_screen),
+        )
+        .add_system_set(
+            SystemSet::on_enter(GameState::NameEntry).with_system(high_score::enter_name_entry),
+        )
+        .add_system_set(
+            SystemSet::on_update(GameState::NameEntry)
+                .with_system(high_score::name_entry)
+                .with_system(high_score::render_name_entry.after(high_score::name_entry)),
+        )
+        .add_system_set(
+            SystemSet::on_exit(GameState::NameEntry)
+                .with_system(high_score::exit_high_score_screen),
+        )
         .add_system_set(SystemSet::on_enter(GameState::GameOver).with_system(game_over::game_over))
+        .add_system_set(
+            SystemSet::on_update(GameState::GameOver)
+                .with_system(menu::back_to_main_menu)
+                .with_system(menu::retry),
+        )
+        .add_system_set(
+            SystemSet::on_exit(GameState::GameOver).with_system(game_over::exit_game_over),
+        )
+        .add_system_set(SystemSet::on_enter(GameState::Won).with_system(game_over::won))
+        .add_system_set(
+            SystemSet::on_update(GameState::Won)
+                .with_system(menu::back_to_main_menu)
+                .with_system(menu::retry),
+        )
+        .add_system_set(SystemSet::on_exit(GameState::Won).with_system(game_over::exit_game_over))
+        .add_system_set(SystemSet::on_enter(GameState::TimeUp).with_system(game_over::time_up))
+        .add_system_set(
+            SystemSet::on_update(GameState::TimeUp)
+                .with_system(menu::back_to_main_menu)
+                .with_system(menu::retry),
+        )
+        .add_system_set(
+            SystemSet::on_exit(GameState::TimeUp).with_system(game_over::exit_game_over),
+        )
+        // the game is only entered when starting, resuming it does not
+        // start a new one
+        .add_system_set(
+            SystemSet::on_enter(GameState::InGame)
+                .with_system(new_game)
+                .with_system(reset_score.after(new_game)),
+        )
         .add_system_set(
             SystemSet::on_update(GameState::InGame)
-                .with_system(move_down)
-                .with_system(move_horizontally)
-                // detect complete line must be executed before move_down
-                // because move down can remove a component that is used within a condition
-                // for line detection. if the detection happens after a collision has been
-                // detected and the PieceSquare has been removed, the line detection system will
-                // not see the removal of the component until next tick
-                .with_system(detect_complete_lines.before(move_down))
-                //.with_system(spawn_new_on_stopped)
-                .with_system(spawn_next_piece)
-                .with_system(move_down_faster)
-                .with_system(rotate)
-                .with_system(stop_fast_move_down_on_collision)
-                .with_system(disappearing_square)
-                .with_system(to_move_below)
-                .with_system(increase_score_and_level)
+                // player inputs are applied to the game before letting the time pass
+                .with_system(move_horizontally.before(update_game))
+                .with_system(move_down_faster.before(update_game))
+                .with_system(hard_drop.before(update_game))
+                .with_system(rotate.before(update_game))
+                .with_system(update_game)
+                // rendering systems must see the squares moved by render_piece
+                // to animate the completed lines
+                .with_system(render_piece.after(update_game))
+                .with_system(render_next_piece.after(update_game))
+                .with_system(render_hold_piece.after(update_game))
+                .with_system(render_ghost_piece.after(update_game))
+                .with_system(garbage::render_garbage.after(update_game))
+                .with_system(puzzle::render_puzzle.after(update_game))
+                .with_system(clear_lines.after(render_piece))
+                // the cleared squares are marked before being despawned, and
+                // moved once the others are gone
+                .with_system(disappearing_square.after(clear_lines))
+                .with_system(square::fade_stack.after(render_piece))
+                .with_system(to_move_below.after(disappearing_square))
+                .with_system(update_score_and_level.after(update_game))
+                .with_system(announce_clear.after(update_game))
+                .with_system(update_play_time.after(update_game))
                 .with_system(dispayable_changed::<Score>)
                 .with_system(dispayable_changed::<Level>)
-                .with_system(dispayable_changed::<LineCompleted>),
+                .with_system(dispayable_changed::<LineCompleted>)
+                .with_system(dispayable_changed::<Combo>)
+                .with_system(dispayable_changed::<BackToBack>)
+                .with_system(dispayable_changed::<PlayTime>)
+                .with_system(dispayable_changed::<MasterGrade>)
+                .with_system(dispayable_changed::<Goal>),
         )
         .add_system_set(SystemSet::on_enter(GameState::Pause).with_system(pause::enter_pause))
+        .add_system_set(
+            SystemSet::on_update(GameState::Pause)
+                .with_system(menu::back_to_main_menu)
+                .with_system(menu::retry),
+        )
         .add_system_set(SystemSet::on_exit(GameState::Pause).with_system(pause::exit_pause))
-        .insert_resource(MoveDownTimer {
-            timer: Timer::from_seconds(Level::default().get_down_duration().as_secs_f32(), true),
-        })
+        // replaced by new_game when leaving the main menu
+        .insert_resource(Game::new(GameConfig::default()))
+        .insert_resource(game_setup)
+        .insert_resource(HighScores::load())
+        .insert_resource(Puzzles::load())
+        // the size of the board displayed, set by new_game
+        .init_resource::<BoardSize>()
+        .init_resource::<NameEntry>()
+        .init_resource::<menu::MenuCursor>()
+        .init_resource::<Settings>()
         .insert_resource(MoveHorizontallyTimer {
             timer: Timer::new(FIRST_REPEAT_DELAY, true),
         })
         .run();
 }
 
+/// Reads the piece generator given on the command line with
+/// `--randomizer <name>`
+fn randomizer_from_args() -> Option<Randomizer> {
+    let randomizer = arg_value("--randomizer")?.unwrap_or_default();
+    match randomizer.parse() {
+        Ok(randomizer) => Some(randomizer),
+        Err(e) => {
+            eprintln!("--randomizer: {}, using the 7 pieces bag", e);
+            None
+        }
+    }
+}
+
+/// Reads the seed given on the command line with `--seed <seed>`
+fn seed_from_args() -> Option<u64> {
+    let seed = arg_value("--seed")?;
+    let seed = seed.and_then(|seed| seed.parse().ok());
+    if seed.is_none() {
+        eprintln!("--seed expects a positive integer, using a random seed");
+    }
+    seed
+}
+
+/// Reads the size of the board given on the command line with
+/// `--board <width>x<height>`
+fn board_size_from_args() -> Option<BoardSize> {
+    let board_size = arg_value("--board")?.unwrap_or_default();
+    match board_size.parse() {
+        Ok(board_size) => Some(board_size),
+        Err(e) => {
+            eprintln!("--board: {}, using the standard board", e);
+            None
+        }
+    }
+}
+
+/// The value following the given option on the command line, `Some(None)`
+/// when the option has no value
+fn arg_value(option: &str) -> Option<Option<String>> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == option {
+            return Some(args.next());
+        }
+    }
+    None
+}
+
 mod board;
+mod engine;
 mod game_over;
+mod garbage;
+mod gravity;
+mod high_score;
+mod master;
+mod menu;
+mod mode;
 mod pause;
 mod piece;
 mod player;
+mod puzzle;
+mod randomizer;
 mod score;
+mod scoring;
+mod settings;
 mod square;
 
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
-    mut spwan_piece_event_writer: EventWriter<SpawnPieceEvent>,
+    game: Res<Game>,
 ) {
     commands.spawn_bundle(Camera2dBundle::default());
 
     spawn_player(&mut commands);
 
-    // setup board
+    spawn_walls(&mut commands, &mut meshes, &mut materials, &game);
+}
 
-    for i in 0..=(BOARD_WIDTH + 1) {
+/// Spawns the walls of the board and of the boxes displaying pieces, for the
+/// size of the board and the number of next pieces of the game
+fn spawn_walls(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    game: &Game,
+) {
+    let board_size = game.board_size();
+    for i in 0..=(board_size.width + 1) {
         spawn_square(
-            &mut commands,
-            &mut meshes,
-            &mut materials,
+            commands,
+            meshes,
+            materials,
             BoardPosition::new(i, 0),
             Color::BLACK,
             Square,
             Some(Wall),
         );
     }
-    for i in 1..=BOARD_HEIGHT {
+    for i in 1..=board_size.height {
         spawn_square(
-            &mut commands,
-            &mut meshes,
-            &mut materials,
+            commands,
+            meshes,
+            materials,
             BoardPosition::new(0, i),
             Color::BLACK,
             Square,
             Some(Wall),
         );
         spawn_square(
-            &mut commands,
-            &mut meshes,
-            &mut materials,
-            BoardPosition::new(BOARD_WIDTH + 1, i),
+            commands,
+            meshes,
+            materials,
+            BoardPosition::new(board_size.width + 1, i),
             Color::BLACK,
             Square,
             Some(Wall),
         );
     }
 
-    // setup next piece walls
+    // setup next & hold piece walls: each piece takes 3 lines
+    let next_pieces = game.next_pieces().len() as i32;
+    let top = piece_box_top(board_size);
+    spawn_piece_box(
+        commands,
+        meshes,
+        materials,
+        next_box_left(board_size),
+        top,
+        3 * next_pieces + 1,
+    );
+    spawn_piece_box(commands, meshes, materials, HOLD_BOX_LEFT, top, 4);
+}
 
+/// Left column of the box displaying the next pieces
+fn next_box_left(board_size: BoardSize) -> i32 {
+    board_size.width + 3
+}
+/// Left column of the box displaying the hold piece
+const HOLD_BOX_LEFT: i32 = -7;
+/// Top line of the boxes displaying pieces
+fn piece_box_top(board_size: BoardSize) -> i32 {
+    board_size.height
+}
+
+/// Spawns the walls of a box displaying pieces, starting at the `left` column
+/// and `top` line, with `lines` lines inside
+fn spawn_piece_box(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    left: i32,
+    top: i32,
+    lines: i32,
+) {
     for i in 0..6 {
         spawn_square(
-            &mut commands,
-            &mut meshes,
-            &mut materials,
-            BoardPosition::new(BOARD_WIDTH + 3 + i, 0),
+            commands,
+            meshes,
+            materials,
+            BoardPosition::new(left + i, top),
             Color::BLACK,
             Square,
             Some(Wall),
         );
         spawn_square(
-            &mut commands,
-            &mut meshes,
-            &mut materials,
-            BoardPosition::new(BOARD_WIDTH + 3 + i, 5),
+            commands,
+            meshes,
+            materials,
+            BoardPosition::new(left + i, top - lines - 1),
             Color::BLACK,
             Square,
             Some(Wall),
         );
     }
-    for i in 0..4 {
+    for i in 1..=lines {
         spawn_square(
-            &mut commands,
-            &mut meshes,
-            &mut materials,
-            BoardPosition::new(BOARD_WIDTH + 3, 1 + i),
+            commands,
+            meshes,
+            materials,
+            BoardPosition::new(left, top - i),
             Color::BLACK,
             Square,
             Some(Wall),
         );
         spawn_square(
-            &mut commands,
-            &mut meshes,
-            &mut materials,
-            BoardPosition::new(BOARD_WIDTH + 3 + 5, 1 + i),
+            commands,
+            meshes,
+            materials,
+            BoardPosition::new(left + 5, top - i),
             Color::BLACK,
             Square,
             Some(Wall),
         );
     }
-
-    // spawn the first piece
-    spwan_piece_event_writer.send_default();
-}
-
-/// The timer used to mode down pieces
-pub struct MoveDownTimer {
-    pub timer: Timer,
-}
-
-impl MoveDownTimer {
-    pub fn speed_up(&mut self) {
-        self.timer.set_duration(FAST_DOWN_DELAY)
-    }
-
-    pub fn normal_speed(&mut self, level: &Level) {
-        self.timer.set_duration(level.get_down_duration())
-    }
 }
 
 /// The timer to move right/left piece while the left or
@@ -208,136 +414,108 @@ struct MoveHorizontallyTimer {
     timer: Timer,
 }
 
-fn move_down(
+/// Starts the game chosen in the main menu, removing what was left on the
+/// board by the previous one: the pieces, the queue and the timers all
+/// belong to the new `Game`
+#[allow(clippy::too_many_arguments)]
+fn new_game(
     mut commands: Commands,
-    time: Res<Time>,
-    mut timer: ResMut<MoveDownTimer>,
-    mut piece_has_stopped_event_writer: EventWriter<PieceHasStoppedEvent>,
-    fixed_query: Query<(&Square, &BoardPosition), Without<PieceSquare>>,
-    mut moving_query: Query<
-        (Entity, &Square, &mut BoardPosition, &mut Transform),
-        With<PieceSquare>,
-    >,
-    mut piece: Query<(Entity, &mut Piece)>,
-    mut state: ResMut<State<GameState>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut game: ResMut<Game>,
+    mut board_size: ResMut<BoardSize>,
+    setup: Res<GameSetup>,
+    puzzles: Res<Puzzles>,
+    squares: Query<Entity, With<Square>>,
 ) {
-    timer.timer.tick(time.delta());
-    if !timer.timer.just_finished() {
-        return;
+    for entity in &squares {
+        commands.entity(entity).despawn_recursive();
     }
-    // check for collisions
-    let mut collided = false;
-    let mut game_over = false;
-    for (_, fixed_bp) in &fixed_query {
-        for (_, _, moving_bp, _) in &moving_query {
-            if fixed_bp.x == moving_bp.x && fixed_bp.y == moving_bp.y {
-                // Collision even before moving the piece: game over!
-                game_over = true;
-            }
-            if fixed_bp.x == moving_bp.x && fixed_bp.y == moving_bp.y - 1 {
-                // collision: the piece must not go down...
-                collided = true;
-            }
-        }
+    *game = Game::new(setup.config(&puzzles));
+    // the walls follow the size of the board and the number of next pieces
+    spawn_walls(&mut commands, &mut meshes, &mut materials, &game);
+    if *board_size != game.board_size() {
+        *board_size = game.board_size();
     }
-    if collided || game_over {
-        // TODO
-        // count score
-        for (entity, _, _, _) in moving_query.iter_mut() {
-            // transform the Piece into fixed squares
-            commands.entity(entity).remove::<PieceSquare>();
-        }
-        // despawn the current piece
-        commands.entity(piece.single().0).despawn();
-
-        if !game_over {
-            // no need to spawn something new on game over
-            piece_has_stopped_event_writer.send_default();
-        } else {
-            // game over
-            let _ = state.set(GameState::GameOver);
-        }
-    } else {
-        for (_, _, mut bp, mut tr) in moving_query.iter_mut() {
-            bp.y -= 1;
-            tr.translation.y -= SQ_TOTAL_SIZE;
-        }
-        for (_, mut piece) in &mut piece {
-            piece.position.y -= 1;
-        }
+    if let Some(puzzle) = game.puzzle() {
+        println!("Puzzle: {}", puzzle.name);
     }
 }
 
-/// When a piece has stopped by hitting something concrete
-#[derive(Default)]
-struct PieceHasStoppedEvent;
+/// Fits the window to the board being displayed
+fn resize_window(board_size: Res<BoardSize>, mut windows: ResMut<Windows>) {
+    if board_size.is_changed() {
+        let size = window_size(*board_size);
+        windows.primary_mut().set_resolution(size.x, size.y);
+    }
+}
 
-fn rotate(
-    input_query: Query<&ActionState<Action>, With<Player>>,
-    mut moving_query: Query<(&Square, &mut BoardPosition, &mut Transform), With<PieceSquare>>,
-    fixed_query: Query<(&Square, &BoardPosition), Without<PieceSquare>>,
-    mut piece: Query<&mut Piece>,
+/// Lets the time pass in the game and forwards what happened to
+/// the rendering systems
+fn update_game(
+    time: Res<Time>,
+    mut game: ResMut<Game>,
+    mut event_writer: EventWriter<GameEvent>,
+    mut state: ResMut<State<GameState>>,
+    high_scores: Res<HighScores>,
+    setup: Res<GameSetup>,
 ) {
-    if let Ok(mut piece) = piece.get_single_mut() {
-        let action = input_query.single();
-        let rotation = if action.just_pressed(Action::RotateAnti) {
-            Some(Rotation::Anti)
-        } else if action.just_pressed(Action::RotateClock) {
-            Some(Rotation::Clock)
-        } else {
-            None
+    game.tick(time.delta());
+    let mut game_over = false;
+    for event in game.drain_events() {
+        game_over |= matches!(event, GameEvent::GameOver(_));
+        event_writer.send(event);
+    }
+    if game_over {
+        // the best games are given a name before the end screen
+        let rank = HighScore::from_game(&game, setup.ranking())
+            .and_then(|high_score| high_scores.rank(setup.mode, &high_score));
+        let next_state = match rank {
+            Some(_) => GameState::NameEntry,
+            None => end_state(&game),
         };
-        if let Some(rotation) = rotation {
-            // gogogo let's go
-            let new_orientation = piece.orientation.apply_rotation(rotation);
-            // check if the new orientation collides with something concrete
-            let new_positions = piece.piece_type.square_pos(new_orientation)
-                + piece.piece_type.anchor()
-                + piece.position;
-
-            let board = fixed_query.iter().map(|(_, bp)| *bp).collect::<Board>();
-            for pos in new_positions {
-                if board.is_concrete(pos) {
-                    // cannot rotate
-                    return;
-                }
-            }
-            // nothing is in the rotation way! let's change the position of all squares
-            let mut moving_square = moving_query.iter_mut();
-            for pos in new_positions {
-                // by construction there are exactly 4 moving square, so we can safely
-                // unwrap at each iteration
-                let (_, mut bp, mut tr) = moving_square.next().unwrap();
-                *bp = pos.into();
-                *tr = bp.as_ref().to_real_position();
-            }
-            piece.orientation = new_orientation;
+        let _ = state.set(next_state);
+    }
+}
 
-            println!("New orientaation: {:?}", piece);
-        }
+/// The screen showing how the game ended
+pub fn end_state(game: &Game) -> GameState {
+    match game.end() {
+        Some(GameEnd::GoalReached) => GameState::Won,
+        Some(GameEnd::TimeUp) => GameState::TimeUp,
+        Some(GameEnd::ToppedOut) | Some(GameEnd::OutOfPieces) | None => GameState::GameOver,
+    }
+}
+
+fn rotate(input_query: Query<&ActionState<Action>, With<Player>>, mut game: ResMut<Game>) {
+    let action = input_query.single();
+    // each action counts, even when pressed in the same frame
+    if action.just_pressed(Action::Hold) {
+        game.step(Input::Hold);
+    }
+    if action.just_pressed(Action::RotateAnti) {
+        game.step(Input::RotateAnti);
+    }
+    if action.just_pressed(Action::RotateClock) {
+        game.step(Input::RotateClock);
     }
 }
 
 fn move_horizontally(
     query: Query<&ActionState<Action>, With<Player>>,
-    fixed_query: Query<(&Square, &BoardPosition), Without<PieceSquare>>,
-    mut moving_query: Query<
-        (Entity, &Square, &mut BoardPosition, &mut Transform),
-        With<PieceSquare>,
-    >,
     mut timer: ResMut<MoveHorizontallyTimer>,
     time: Res<Time>,
-    mut piece: Query<&mut Piece>,
+    mut game: ResMut<Game>,
 ) {
     timer.timer.tick(time.delta());
 
     let action_state = query.single();
 
     // Just pressed the key
-    let mut direction: Option<i32> = if action_state.just_pressed(Action::Left) {
-        Some(-1)
+    let mut direction: Option<Input> = if action_state.just_pressed(Action::Left) {
+        Some(Input::Left)
     } else if action_state.just_pressed(Action::Right) {
-        Some(1)
+        Some(Input::Right)
     } else {
         None
     };
@@ -358,105 +536,85 @@ fn move_horizontally(
                 }
                 // recompute direction if keys are still pressed
                 if action_state.pressed(Action::Left) {
-                    direction = Some(-1);
+                    direction = Some(Input::Left);
                 } else if action_state.pressed(Action::Right) {
-                    direction = Some(1);
+                    direction = Some(Input::Right);
                 }
             }
         }
     }
 
     if let Some(direction) = direction {
-        let mut collided = false;
-        'outer: for (_, fixed_bp) in &fixed_query {
-            for (_, _, moving_bp, _) in &moving_query {
-                if fixed_bp.x == moving_bp.x + direction && fixed_bp.y == moving_bp.y {
-                    // collision: the piece must not go left or right...
-                    collided = true;
-                    break 'outer;
-                }
-            }
-        }
-        if !collided {
-            for (_, _, mut bp, mut tr) in moving_query.iter_mut() {
-                bp.x += direction;
-                tr.translation.x += SQ_TOTAL_SIZE * direction as f32;
-            }
-            // there is 0 (gane over) or 1 (running) piece on the board...
-            for mut piece in &mut piece {
-                piece.position.x += direction;
-            }
-        }
+        game.step(direction);
     }
 }
 
-fn move_down_faster(
-    level: Query<&Level>,
-    mut timer: ResMut<MoveDownTimer>,
-    query: Query<&ActionState<Action>, With<Player>>,
-) {
+fn move_down_faster(query: Query<&ActionState<Action>, With<Player>>, mut game: ResMut<Game>) {
     let action_state = query.single();
 
     if action_state.just_pressed(Action::Down) {
-        timer.speed_up();
+        game.step(Input::SoftDrop);
     }
     if action_state.just_released(Action::Down) {
-        timer.normal_speed(&level.single());
+        game.step(Input::ReleaseSoftDrop);
     }
 }
 
-fn stop_fast_move_down_on_collision(
-    level: Query<&Level>,
-    mut timer: ResMut<MoveDownTimer>,
-    mut event_reader: EventReader<PieceHasStoppedEvent>,
-) {
-    for _ev in event_reader.iter() {
-        timer.normal_speed(&level.single())
+fn hard_drop(query: Query<&ActionState<Action>, With<Player>>, mut game: ResMut<Game>) {
+    if query.single().just_pressed(Action::HardDrop) {
+        game.step(Input::HardDrop);
     }
 }
 
-fn detect_complete_lines(
+/// Squares of the stack, walls and previews left out
+type StackFilter = (
+    With<Square>,
+    Without<Wall>,
+    Without<NextPieceSquare>,
+    Without<HoldPieceSquare>,
+    Without<GhostSquare>,
+);
+
+/// Animates the lines removed from the game
+fn clear_lines(
     mut commands: Commands,
-    mut event_reader: EventReader<PieceHasStoppedEvent>,
-    mut spawn_piece_writer: EventWriter<SpawnPieceEvent>,
-    mut fixed_query: Query<
-        (Entity, &Square, &BoardPosition),
-        (Without<PieceSquare>, Without<Wall>),
-    >,
-    mut line_completed: EventWriter<LinesCompletedEvent>,
+    mut event_reader: EventReader<GameEvent>,
+    // the squares of the piece that has just been locked may still have
+    // their PieceSquare component: it is removed at the end of the frame
+    fixed_query: Query<(Entity, &BoardPosition), StackFilter>,
 ) {
-    for _ev in event_reader.iter() {
-        let board = fixed_query.iter().map(|(_, _, bp)| *bp).collect::<Board>();
-
-        let full_lines = (1..=BOARD_HEIGHT)
-            .into_iter()
-            .filter(|line| board.is_line_full(*line))
-            .collect::<Vec<_>>();
-
-        //println!("BOARD:\n{}", board);
-        println!("Full lines: {:?}", full_lines);
-
-        if full_lines.is_empty() {
-            spawn_piece_writer.send_default()
-        } else {
-            line_completed.send(LinesCompletedEvent(full_lines.len()));
-            // new piece will be spawned by the end of the animation
-            for (entity, _, bp) in &mut fixed_query {
+    let events: Vec<&GameEvent> = event_reader.iter().collect();
+    // after a cascade clear only the squares given by the game fall
+    let falls = events.iter().find_map(|event| match event {
+        GameEvent::ClustersFell(falls) => Some(falls),
+        _ => None,
+    });
+    for event in events.iter() {
+        if let GameEvent::BoardCleared = event {
+            for (entity, _) in &fixed_query {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        if let GameEvent::LinesCleared(full_lines) = event {
+            println!("Full lines: {:?}", full_lines);
+            for (entity, bp) in &fixed_query {
                 if full_lines.contains(&bp.y) {
                     // start animation on squares from completed lines!
-                    // at the end of the animation, the new piece will be spawn
                     commands
                         .entity(entity)
                         .insert(DisappearingSquare::default());
                 } else {
-                    // how many lines have been completed below the current
-                    // position?
-                    let completed_below =
-                        full_lines.iter().filter(|line| **line < bp.y).count() as i32;
+                    let lines_below = match falls {
+                        Some(falls) => falls
+                            .iter()
+                            .find(|(position, _)| position == bp)
+                            .map_or(0, |(_, fall)| *fall),
+                        // how many lines have been completed below the current
+                        // position?
+                        None => full_lines.iter().filter(|line| **line < bp.y).count() as i32,
+                    };
                     // move down!!!
-                    commands.entity(entity).insert(ToMoveBelow(completed_below));
-                    //bp.y -= completed_below;
-                    //tr.translation.y -= completed_below as f32 * SQ_TOTAL_SIZE;
+                    commands.entity(entity).insert(ToMoveBelow(lines_below));
                 }
             }
         }
@@ -0,0 +1,105 @@
+//! Rules of the arcade master games: sections of 100 levels, shorter delays
+//! in each section and a grade earned with the score.
+
+use std::{fmt::Display, time::Duration};
+
+/// The game is won when reaching this level
+pub const MAX_LEVEL: usize = 999;
+
+/// Delays of the pieces in a section
+#[derive(Clone, Copy, Debug)]
+pub struct SectionTiming {
+    /// Before the next piece enters the board
+    pub are: Duration,
+    /// Before the next piece enters the board, after a line clear
+    pub line_are: Duration,
+    pub lock_delay: Duration,
+    /// Time the cleared lines take to disappear
+    pub line_clear: Duration,
+}
+
+/// Delays of the section of the level
+pub fn section_timing(level: usize) -> SectionTiming {
+    // from the given level, in frames: ARE, line ARE, lock delay, line clear
+    const TABLE: [(usize, u64, u64, u64, u64); 6] = [
+        (0, 25, 25, 30, 40),
+        (500, 25, 25, 30, 25),
+        (600, 25, 16, 30, 16),
+        (700, 16, 12, 30, 12),
+        (800, 12, 6, 30, 6),
+        (900, 12, 6, 17, 6),
+    ];
+    let (_, are, line_are, lock_delay, line_clear) = *TABLE
+        .iter()
+        .rev()
+        .find(|(from_level, ..)| level >= *from_level)
+        .unwrap_or(&TABLE[0]);
+    SectionTiming {
+        are: frames(are),
+        line_are: frames(line_are),
+        lock_delay: frames(lock_delay),
+        line_clear: frames(line_clear),
+    }
+}
+
+fn frames(frames: u64) -> Duration {
+    Duration::from_micros(frames * 1_000_000 / 60)
+}
+
+/// Points needed for each grade, from 9 to S9
+const GRADE_SCORES: [usize; 18] = [
+    0, 400, 800, 1400, 2000, 3500, 5500, 8000, 12000, 16000, 22000, 30000, 40000, 52000, 66000,
+    82000, 100000, 120000,
+];
+const GRADE_NAMES: [&str; 19] = [
+    "9", "8", "7", "6", "5", "4", "3", "2", "1", "S1", "S2", "S3", "S4", "S5", "S6", "S7", "S8",
+    "S9", "GM",
+];
+/// To become a grand master, the player must reach these levels with
+/// these scores in time
+const GRAND_MASTER_CHECKPOINTS: [(usize, usize, Duration); 3] = [
+    (300, 12000, Duration::from_secs(4 * 60 + 15)),
+    (500, 40000, Duration::from_secs(7 * 60 + 30)),
+    (MAX_LEVEL, 126000, Duration::from_secs(13 * 60 + 30)),
+];
+
+/// Index in the grade names, from 9 to GM
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Grade(usize);
+
+impl Display for Grade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", GRADE_NAMES[self.0])
+    }
+}
+
+/// Follows the performance of the player to compute the grade
+pub struct Grading {
+    /// have all the checkpoints been passed so far?
+    grand_master: bool,
+}
+
+impl Default for Grading {
+    fn default() -> Self {
+        Self { grand_master: true }
+    }
+}
+
+impl Grading {
+    /// Checks the grand master checkpoints passed when going from
+    /// `previous_level` to `level`
+    pub fn update(&mut self, previous_level: usize, level: usize, score: usize, elapsed: Duration) {
+        for (checkpoint, min_score, max_time) in GRAND_MASTER_CHECKPOINTS {
+            if previous_level < checkpoint && level >= checkpoint {
+                self.grand_master &= score >= min_score && elapsed <= max_time;
+            }
+        }
+    }
+
+    pub fn grade(&self, level: usize, score: usize) -> Grade {
+        if self.grand_master && level >= MAX_LEVEL {
+            return Grade(GRADE_NAMES.len() - 1);
+        }
+        Grade(GRADE_SCORES.iter().filter(|min| score >= **min).count() - 1)
+    }
+}
@@ -0,0 +1,536 @@
+//! The main menu, shown when the app starts and between games.
+
+use std::time::Duration;
+
+use bevy::{
+    app::AppExit,
+    prelude::{shape::Quad, *},
+    sprite::MaterialMesh2dBundle,
+};
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::{
+    board::BoardSize,
+    engine::{GameConfig, MAX_NEXT_PIECES},
+    gravity::GravityCurve,
+    mode::{GameMode, ModeSettings, Ranking},
+    player::{Action, Player},
+    puzzle::Puzzles,
+    randomizer::Randomizer,
+    settings::Settings,
+    window_size, GameState,
+};
+
+/// Highest level a game can be started at
+const MAX_START_LEVEL: usize = 20;
+/// Lines added to or removed from the goal of a sprint at once
+const SPRINT_LINES_STEP: usize = 10;
+const MAX_SPRINT_LINES: usize = 200;
+/// Seconds added to or removed from an ultra game at once
+const ULTRA_SECONDS_STEP: usize = 60;
+const MAX_ULTRA_SECONDS: usize = 600;
+/// Garbage rows of a dig game, a small board holds less of them
+const MAX_DIG_ROWS: usize = 20;
+/// Height of the first entry of the menu, below the title
+const FIRST_ENTRY_Y: f32 = 60.;
+/// Vertical space between two entries of the menu
+const ENTRY_HEIGHT: f32 = 24.;
+/// Boards that can be chosen: standard, 4-wide and large
+const BOARD_SIZES: [BoardSize; 3] = [
+    BoardSize::STANDARD,
+    BoardSize::new(4, 25),
+    BoardSize::new(20, 40),
+];
+
+/// The next game to play, as chosen in the main menu
+pub struct GameSetup {
+    pub mode: GameMode,
+    pub start_level: usize,
+    /// Can only be turned off in zen games and puzzles
+    pub gravity: bool,
+    /// Index of the puzzle to solve in the [`Puzzles`]
+    pub puzzle: usize,
+    /// Goals of the sprint, ultra and dig games
+    pub mode_settings: ModeSettings,
+    /// Replaces the randomizer of the mode, puzzles have their own pieces
+    pub randomizer: Option<Randomizer>,
+    /// Replaces the number of upcoming pieces shown by the mode
+    pub next_pieces: Option<usize>,
+    /// Puzzles are played on their own board
+    pub board_size: BoardSize,
+    /// Size given on the command line, offered along with [`BOARD_SIZES`]
+    pub custom_board_size: Option<BoardSize>,
+    /// Seed given on the command line, every game is played with it
+    pub seed: Option<u64>,
+}
+
+impl Default for GameSetup {
+    fn default() -> Self {
+        Self {
+            mode: GameMode::default(),
+            start_level: 1,
+            gravity: true,
+            puzzle: 0,
+            mode_settings: ModeSettings::default(),
+            randomizer: None,
+            next_pieces: None,
+            board_size: BoardSize::default(),
+            custom_board_size: None,
+            seed: None,
+        }
+    }
+}
+
+impl GameSetup {
+    pub fn config(&self, puzzles: &Puzzles) -> GameConfig {
+        let mut config = self.mode.config(&self.mode_settings);
+        if self.mode.has_start_level() {
+            config.start_level = self.start_level;
+        }
+        if self.mode.optional_gravity() && !self.gravity {
+            config.gravity = GravityCurve::Off;
+        }
+        if self.mode == GameMode::Puzzle {
+            config.puzzle = puzzles.0.get(self.puzzle).cloned();
+        }
+        if let Some(randomizer) = self.randomizer {
+            config.randomizer = randomizer;
+        }
+        config.next_pieces = self.next_pieces();
+        config.board_size = self.board_size();
+        if let Some(seed) = self.seed {
+            config.seed = seed;
+        }
+        config
+    }
+
+    /// How the game is compared to the others: with other pieces or
+    /// another goal than the ones of its mode, it is not
+    pub fn ranking(&self) -> Ranking {
+        let config = self.mode.config(&self.mode_settings);
+        let default_config = self.mode.config(&ModeSettings::default());
+        let other_pieces = self
+            .randomizer
+            .is_some_and(|randomizer| randomizer != default_config.randomizer);
+        let other_goal = config.line_goal != default_config.line_goal
+            || config.time_limit != default_config.time_limit
+            || config.garbage != default_config.garbage;
+        if other_pieces || other_goal {
+            Ranking::Unranked
+        } else {
+            self.mode.ranking()
+        }
+    }
+
+    /// How many upcoming pieces are shown: the chosen number, or the one of
+    /// the mode
+    pub fn next_pieces(&self) -> usize {
+        self.next_pieces
+            .unwrap_or_else(|| self.mode.config(&self.mode_settings).next_pieces)
+    }
+
+    /// The size of the board the game is played on: the chosen one, grown if
+    /// it is too small for the mode
+    pub fn board_size(&self) -> BoardSize {
+        self.board_size.at_least(self.mode.min_board_size())
+    }
+
+    /// The sizes the board can be given in the menu, the ones too small for
+    /// the mode left out
+    fn board_sizes(&self) -> Vec<BoardSize> {
+        let min_board_size = self.mode.min_board_size();
+        let mut board_sizes = BOARD_SIZES.to_vec();
+        if let Some(custom_board_size) = self.custom_board_size {
+            if !board_sizes.contains(&custom_board_size) {
+                board_sizes.push(custom_board_size);
+            }
+        }
+        board_sizes.retain(|board_size| board_size.at_least(min_board_size) == *board_size);
+        board_sizes
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MenuItem {
+    Play,
+    Mode,
+    Level,
+    /// Lines of a sprint, time of an ultra game, garbage rows of a dig game
+    Goal,
+    Messiness,
+    HoleChange,
+    Pieces,
+    NextPieces,
+    Board,
+    Gravity,
+    GhostPiece,
+    HighScores,
+    Quit,
+}
+
+const MENU_ITEMS: [MenuItem; 13] = [
+    MenuItem::Play,
+    MenuItem::Mode,
+    MenuItem::Level,
+    MenuItem::Goal,
+    MenuItem::Messiness,
+    MenuItem::HoleChange,
+    MenuItem::Pieces,
+    MenuItem::NextPieces,
+    MenuItem::Board,
+    MenuItem::Gravity,
+    MenuItem::GhostPiece,
+    MenuItem::HighScores,
+    MenuItem::Quit,
+];
+
+/// Index in [`MENU_ITEMS`] of the selected entry
+#[derive(Default)]
+pub struct MenuCursor(usize);
+
+/// Marker component: everything displayed by the main menu
+#[derive(Component)]
+pub struct MainMenu;
+
+#[derive(Component)]
+pub struct MenuEntry(MenuItem);
+
+#[allow(clippy::too_many_arguments)]
+pub fn enter_main_menu(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    cursor: Res<MenuCursor>,
+    setup: Res<GameSetup>,
+    settings: Res<Settings>,
+    puzzles: Res<Puzzles>,
+    board_size: Res<BoardSize>,
+) {
+    let font = asset_server.load("FiraCode-Regular.ttf");
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                "OXIDIZED\nTETRIS",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 64.0,
+                    color: Color::ANTIQUE_WHITE,
+                },
+            )
+            .with_alignment(TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Center,
+            }),
+            transform: Transform::from_translation(Vec3::new(0., 185., 10.)),
+            ..default()
+        })
+        .insert(MainMenu);
+
+    let text_style = TextStyle {
+        font,
+        font_size: 22.0,
+        color: Color::GRAY,
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Center,
+        horizontal: HorizontalAlign::Left,
+    };
+    for (index, item) in MENU_ITEMS.iter().enumerate() {
+        let mut text = Text::from_section("", text_style.clone()).with_alignment(text_alignment);
+        display_entry(&mut text, *item, &cursor, &setup, &settings, &puzzles);
+        commands
+            .spawn_bundle(Text2dBundle {
+                text,
+                transform: Transform::from_translation(Vec3::new(
+                    -150.,
+                    FIRST_ENTRY_Y - ENTRY_HEIGHT * index as f32,
+                    10.,
+                )),
+                ..default()
+            })
+            .insert(MainMenu)
+            .insert(MenuEntry(*item));
+    }
+
+    // hides the board
+    commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            mesh: meshes
+                .add(
+                    Quad {
+                        size: window_size(*board_size),
+                        ..Default::default()
+                    }
+                    .into(),
+                )
+                .into(),
+            material: materials.add(ColorMaterial::from(Color::BLACK)),
+            transform: Transform::from_translation(Vec3::new(0., 0., 5.)),
+            ..default()
+        })
+        .insert(MainMenu);
+}
+
+pub fn exit_main_menu(mut commands: Commands, menu_elements: Query<Entity, With<MainMenu>>) {
+    for e in &menu_elements {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+/// Moves the cursor with up & down, changes the selected value with left &
+/// right
+pub fn main_menu(
+    mut input_query: Query<&mut ActionState<Action>, With<Player>>,
+    mut cursor: ResMut<MenuCursor>,
+    mut setup: ResMut<GameSetup>,
+    mut settings: ResMut<Settings>,
+    puzzles: Res<Puzzles>,
+    mut state: ResMut<State<GameState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let mut input = input_query.single_mut();
+    if input.just_pressed(Action::Up) {
+        cursor.0 = (cursor.0 + MENU_ITEMS.len() - 1) % MENU_ITEMS.len();
+    }
+    if input.just_pressed(Action::Down) {
+        cursor.0 = (cursor.0 + 1) % MENU_ITEMS.len();
+    }
+
+    let item = MENU_ITEMS[cursor.0];
+    // values can also be changed with select, for gamepads
+    let step = if input.just_pressed(Action::Left) {
+        -1
+    } else if input.just_pressed(Action::Right) || input.just_pressed(Action::Select) {
+        1
+    } else {
+        0
+    };
+    match item {
+        // there is nothing to play without puzzle files
+        MenuItem::Play if setup.mode == GameMode::Puzzle && puzzles.0.is_empty() => (),
+        MenuItem::Play if input.just_pressed(Action::Select) => {
+            input.consume(Action::Select);
+            // another input of the frame may have changed the state already
+            let _ = state.set(GameState::InGame);
+        }
+        MenuItem::HighScores if input.just_pressed(Action::Select) => {
+            input.consume(Action::Select);
+            let _ = state.set(GameState::HighScores);
+        }
+        MenuItem::Quit if input.just_pressed(Action::Select) => exit.send(AppExit),
+        MenuItem::Mode if step != 0 => setup.mode = setup.mode.cycle(step),
+        MenuItem::Level if step != 0 && setup.mode == GameMode::Puzzle && !puzzles.0.is_empty() => {
+            setup.puzzle =
+                (setup.puzzle as isize + step).rem_euclid(puzzles.0.len() as isize) as usize;
+        }
+        MenuItem::Level if step != 0 && setup.mode.has_start_level() => {
+            setup.start_level =
+                (setup.start_level as isize + step).clamp(1, MAX_START_LEVEL as isize) as usize;
+        }
+        MenuItem::Goal if step != 0 => {
+            let mode = setup.mode;
+            let settings = &mut setup.mode_settings;
+            match mode {
+                GameMode::Sprint => {
+                    settings.sprint_lines = stepped(
+                        settings.sprint_lines,
+                        step,
+                        SPRINT_LINES_STEP,
+                        MAX_SPRINT_LINES,
+                    )
+                }
+                GameMode::Ultra => {
+                    let seconds = stepped(
+                        settings.ultra_duration.as_secs() as usize,
+                        step,
+                        ULTRA_SECONDS_STEP,
+                        MAX_ULTRA_SECONDS,
+                    );
+                    settings.ultra_duration = Duration::from_secs(seconds as u64);
+                }
+                GameMode::Dig => {
+                    settings.dig_garbage.rows =
+                        stepped(settings.dig_garbage.rows, step, 1, MAX_DIG_ROWS)
+                }
+                _ => (),
+            }
+        }
+        MenuItem::Messiness if step != 0 && setup.mode == GameMode::Dig => {
+            let garbage = &mut setup.mode_settings.dig_garbage;
+            garbage.messiness = stepped_probability(garbage.messiness, step);
+        }
+        MenuItem::HoleChange if step != 0 && setup.mode == GameMode::Dig => {
+            let garbage = &mut setup.mode_settings.dig_garbage;
+            garbage.hole_change = stepped_probability(garbage.hole_change, step);
+        }
+        MenuItem::NextPieces if step != 0 => {
+            setup.next_pieces = Some(stepped(setup.next_pieces(), step, 1, MAX_NEXT_PIECES))
+        }
+        MenuItem::Pieces if step != 0 && setup.mode != GameMode::Puzzle => {
+            // the randomizer of the mode comes first
+            let randomizers: Vec<Option<Randomizer>> = [None]
+                .into_iter()
+                .chain(Randomizer::ALL.into_iter().map(Some))
+                .collect();
+            let index = randomizers
+                .iter()
+                .position(|randomizer| *randomizer == setup.randomizer)
+                .unwrap_or(0);
+            setup.randomizer = randomizers
+                [(index as isize + step).rem_euclid(randomizers.len() as isize) as usize];
+        }
+        MenuItem::Board if step != 0 && setup.mode != GameMode::Puzzle => {
+            let board_sizes = setup.board_sizes();
+            let index = board_sizes
+                .iter()
+                .position(|board_size| *board_size == setup.board_size())
+                .unwrap_or(0);
+            setup.board_size = board_sizes
+                [(index as isize + step).rem_euclid(board_sizes.len() as isize) as usize];
+        }
+        MenuItem::Gravity if step != 0 && setup.mode.optional_gravity() => {
+            setup.gravity = !setup.gravity
+        }
+        MenuItem::GhostPiece if step != 0 => settings.ghost_piece = !settings.ghost_piece,
+        _ => (),
+    }
+}
+
+pub fn render_main_menu(
+    cursor: Res<MenuCursor>,
+    setup: Res<GameSetup>,
+    settings: Res<Settings>,
+    puzzles: Res<Puzzles>,
+    mut query: Query<(&MenuEntry, &mut Text)>,
+) {
+    if !cursor.is_changed() && !setup.is_changed() && !settings.is_changed() {
+        return;
+    }
+    for (entry, mut text) in &mut query {
+        display_entry(&mut text, entry.0, &cursor, &setup, &settings, &puzzles);
+    }
+}
+
+fn display_entry(
+    text: &mut Text,
+    item: MenuItem,
+    cursor: &MenuCursor,
+    setup: &GameSetup,
+    settings: &Settings,
+    puzzles: &Puzzles,
+) {
+    let section = &mut text.sections[0];
+    section.value = match item {
+        MenuItem::Play => "PLAY".to_string(),
+        MenuItem::Mode => format!("MODE    < {} >", setup.mode),
+        MenuItem::Level if setup.mode == GameMode::Puzzle => match puzzles.0.get(setup.puzzle) {
+            Some(puzzle) => format!("PUZZLE  < {} >", puzzle.name),
+            None => "PUZZLE    NONE".to_string(),
+        },
+        MenuItem::Level if setup.mode == GameMode::Master => "LEVEL     00".to_string(),
+        MenuItem::Level => format!("LEVEL   < {:02} >", setup.start_level),
+        MenuItem::Goal => {
+            let settings = &setup.mode_settings;
+            match setup.mode {
+                GameMode::Sprint => format!("LINES   < {} >", settings.sprint_lines),
+                GameMode::Ultra => {
+                    format!("TIME    < {}:00 >", settings.ultra_duration.as_secs() / 60)
+                }
+                GameMode::Dig => format!("ROWS    < {} >", settings.dig_garbage.rows),
+                GameMode::Master => "GOAL      LEVEL 999".to_string(),
+                GameMode::Puzzle => match puzzles.0.get(setup.puzzle) {
+                    Some(puzzle) => format!("GOAL      {}", puzzle.goal).replace('\n', " "),
+                    None => "GOAL      NONE".to_string(),
+                },
+                _ => "GOAL      NONE".to_string(),
+            }
+        }
+        // only dig games have garbage
+        MenuItem::Messiness if setup.mode != GameMode::Dig => "MESSY     --".to_string(),
+        MenuItem::Messiness => format!(
+            "MESSY   < {} >",
+            percent(setup.mode_settings.dig_garbage.messiness)
+        ),
+        MenuItem::HoleChange if setup.mode != GameMode::Dig => "SHIFT     --".to_string(),
+        MenuItem::HoleChange => format!(
+            "SHIFT   < {} >",
+            percent(setup.mode_settings.dig_garbage.hole_change)
+        ),
+        MenuItem::NextPieces => format!("NEXT    < {} >", setup.next_pieces()),
+        MenuItem::Pieces if setup.mode == GameMode::Puzzle => "PIECES    FIXED".to_string(),
+        MenuItem::Pieces => match setup.randomizer {
+            Some(randomizer) => format!("PIECES  < {} >", randomizer),
+            None => "PIECES  < MODE >".to_string(),
+        },
+        MenuItem::Board if setup.mode == GameMode::Puzzle => format!(
+            "BOARD     {}",
+            puzzles
+                .0
+                .get(setup.puzzle)
+                .map_or(BoardSize::STANDARD, |puzzle| puzzle.board_size)
+        ),
+        MenuItem::Board => format!("BOARD   < {} >", setup.board_size()),
+        // other modes always have gravity
+        MenuItem::Gravity if !setup.mode.optional_gravity() => "GRAVITY   ON".to_string(),
+        MenuItem::Gravity => format!("GRAVITY < {} >", on_off(setup.gravity)),
+        MenuItem::GhostPiece => format!("GHOST   < {} >", on_off(settings.ghost_piece)),
+        MenuItem::HighScores => "HIGH SCORES".to_string(),
+        MenuItem::Quit => "QUIT".to_string(),
+    };
+    section.style.color = if MENU_ITEMS[cursor.0] == item {
+        Color::ANTIQUE_WHITE
+    } else {
+        Color::GRAY
+    };
+}
+
+/// The value moved by `step` units, kept between one unit and `max`
+fn stepped(value: usize, step: isize, unit: usize, max: usize) -> usize {
+    (value as isize + step * unit as isize).clamp(unit as isize, max as isize) as usize
+}
+
+/// The probability moved by `step` tenths, kept between 0 and 1
+fn stepped_probability(probability: f64, step: isize) -> f64 {
+    ((probability * 10.).round() as isize + step).clamp(0, 10) as f64 / 10.
+}
+
+fn percent(probability: f64) -> String {
+    format!("{}%", (probability * 100.).round())
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "ON"
+    } else {
+        "OFF"
+    }
+}
+
+/// Leaves a paused or finished game
+pub fn back_to_main_menu(
+    mut input_query: Query<&mut ActionState<Action>, With<Player>>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let mut input = input_query.single_mut();
+    if input.just_pressed(Action::Select) {
+        // the main menu is updated within the same frame, it must not see
+        // select again
+        input.consume(Action::Select);
+        // the paused game is dropped from the stack too. Retrying in the
+        // same frame may have queued its state first, then it wins.
+        let _ = state.replace(GameState::MainMenu);
+    }
+}
+
+/// Starts a paused or finished game again, with the same rules
+pub fn retry(
+    input_query: Query<&ActionState<Action>, With<Player>>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let input = input_query.single();
+    if input.just_pressed(Action::Retry) {
+        // leaving in the same frame may have queued its state first
+        let _ = state.replace(GameState::InGame);
+    }
+}
@@ -0,0 +1,297 @@
+//! The different ways to play.
+
+use std::{fmt::Display, time::Duration};
+
+use crate::{
+    board::BoardSize,
+    engine::{ClearGravity, GameConfig, LevelProgression, LockReset, TopOut},
+    garbage::Garbage,
+    gravity::GravityCurve,
+    randomizer::Randomizer,
+    scoring::Scoring,
+};
+
+/// Lines to clear in a sprint, unless changed in the main menu
+pub const SPRINT_LINES: usize = 40;
+/// How long an ultra game lasts, unless changed in the main menu
+pub const ULTRA_DURATION: Duration = Duration::from_secs(120);
+/// How long the locked squares stay visible in a fading game
+pub const FADING_DELAY: Duration = Duration::from_secs(3);
+/// Garbage to clear in a dig game, unless changed in the main menu
+pub const DIG_GARBAGE: Garbage = Garbage {
+    rows: 10,
+    messiness: 1.,
+    hole_change: 0.7,
+};
+
+/// The goals of the modes that can be changed in the main menu
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModeSettings {
+    pub sprint_lines: usize,
+    pub ultra_duration: Duration,
+    pub dig_garbage: Garbage,
+}
+
+impl Default for ModeSettings {
+    fn default() -> Self {
+        Self {
+            sprint_lines: SPRINT_LINES,
+            ultra_duration: ULTRA_DURATION,
+            dig_garbage: DIG_GARBAGE,
+        }
+    }
+}
+
+/// The games offered by the main menu
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum GameMode {
+    /// Modern rules, the game goes on until the stack tops out
+    #[default]
+    Marathon,
+    /// The rules of the NES game
+    Classic,
+    /// The rules of the Sega arcade game
+    Sega,
+    /// Clearing a number of lines as fast as possible, [`SPRINT_LINES`]
+    /// by default
+    Sprint,
+    /// Making the highest score in a given time, [`ULTRA_DURATION`] by
+    /// default
+    Ultra,
+    /// Clearing rows of garbage as fast as possible, [`DIG_GARBAGE`] by
+    /// default
+    Dig,
+    /// Playing without pressure: the level does not change and the board
+    /// is cleared instead of topping out
+    Zen,
+    /// The arcade master rules: levels go up to 999 by sections, the delays
+    /// get shorter and the player earns a grade
+    Master,
+    /// Marathon rules, but the locked squares disappear at once
+    Invisible,
+    /// Marathon rules, but the locked squares fade out after
+    /// [`FADING_DELAY`]
+    Fading,
+    /// Solving the puzzles of the level files
+    Puzzle,
+    /// Marathon rules, but the squares left by a clear fall in groups and
+    /// can clear more lines in a chain
+    Cascade,
+    /// Marathon rules with pieces made of 2x2 blocks
+    Big,
+}
+
+/// How the games of a mode are compared
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ranking {
+    /// The highest score wins
+    Score,
+    /// The fastest game to reach the goal wins
+    Time,
+    /// The games are not compared
+    Unranked,
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 13] = [
+        GameMode::Marathon,
+        GameMode::Classic,
+        GameMode::Sega,
+        GameMode::Sprint,
+        GameMode::Ultra,
+        GameMode::Dig,
+        GameMode::Zen,
+        GameMode::Master,
+        GameMode::Invisible,
+        GameMode::Fading,
+        GameMode::Puzzle,
+        GameMode::Cascade,
+        GameMode::Big,
+    ];
+
+    /// Name of the mode in the files, must never change
+    pub fn id(&self) -> &'static str {
+        match self {
+            GameMode::Marathon => "marathon",
+            GameMode::Classic => "classic",
+            GameMode::Sega => "sega",
+            GameMode::Sprint => "sprint",
+            GameMode::Ultra => "ultra",
+            GameMode::Dig => "dig",
+            GameMode::Zen => "zen",
+            GameMode::Master => "master",
+            GameMode::Invisible => "invisible",
+            GameMode::Fading => "fading",
+            GameMode::Puzzle => "puzzle",
+            GameMode::Cascade => "cascade",
+            GameMode::Big => "big",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        GameMode::ALL.into_iter().find(|mode| mode.id() == id)
+    }
+
+    /// The mode `step` places after this one in [`GameMode::ALL`], going
+    /// round
+    pub fn cycle(&self, step: isize) -> Self {
+        let modes = GameMode::ALL.len() as isize;
+        let index = GameMode::ALL
+            .iter()
+            .position(|mode| mode == self)
+            .unwrap_or(0) as isize;
+        GameMode::ALL[(index + step).rem_euclid(modes) as usize]
+    }
+
+    /// The smallest board the mode can be played on
+    pub fn min_board_size(&self) -> BoardSize {
+        match self {
+            // the big bar is 8 squares long and must still be able to turn
+            GameMode::Big => BoardSize::new(BoardSize::STANDARD.width, BoardSize::MIN.height),
+            _ => BoardSize::MIN,
+        }
+    }
+
+    pub fn ranking(&self) -> Ranking {
+        match self {
+            GameMode::Marathon
+            | GameMode::Classic
+            | GameMode::Sega
+            | GameMode::Ultra
+            | GameMode::Master
+            | GameMode::Invisible
+            | GameMode::Fading
+            | GameMode::Cascade
+            | GameMode::Big => Ranking::Score,
+            GameMode::Sprint | GameMode::Dig => Ranking::Time,
+            // zen games never end and each puzzle is a different game, they
+            // have no high score
+            GameMode::Zen | GameMode::Puzzle => Ranking::Unranked,
+        }
+    }
+
+    /// Is the level chosen in the main menu?
+    pub fn has_start_level(&self) -> bool {
+        // master games start from the first section, puzzles have no level
+        !matches!(self, GameMode::Master | GameMode::Puzzle)
+    }
+
+    /// Can the gravity be turned off in the main menu?
+    pub fn optional_gravity(&self) -> bool {
+        matches!(self, GameMode::Zen | GameMode::Puzzle)
+    }
+
+    /// How long the locked squares stay visible before fading out, `None`
+    /// when the stack is always visible
+    pub fn stack_fade(&self) -> Option<Duration> {
+        match self {
+            GameMode::Invisible => Some(Duration::ZERO),
+            GameMode::Fading => Some(FADING_DELAY),
+            _ => None,
+        }
+    }
+
+    /// The rules the mode is played with, its goal taken from the settings
+    pub fn config(&self, settings: &ModeSettings) -> GameConfig {
+        match self {
+            GameMode::Marathon | GameMode::Invisible | GameMode::Fading => GameConfig::default(),
+            GameMode::Cascade => GameConfig {
+                clear_gravity: ClearGravity::Cascade,
+                ..GameConfig::default()
+            },
+            GameMode::Big => GameConfig {
+                big: true,
+                ..GameConfig::default()
+            },
+            // the puzzle itself is chosen in the main menu
+            GameMode::Puzzle => GameConfig {
+                level_progression: LevelProgression::Fixed,
+                ..GameConfig::default()
+            },
+            GameMode::Sprint => GameConfig {
+                line_goal: Some(settings.sprint_lines),
+                ..GameConfig::default()
+            },
+            GameMode::Ultra => GameConfig {
+                time_limit: Some(settings.ultra_duration),
+                ..GameConfig::default()
+            },
+            GameMode::Dig => GameConfig {
+                garbage: Some(settings.dig_garbage),
+                ..GameConfig::default()
+            },
+            GameMode::Zen => GameConfig {
+                level_progression: LevelProgression::Fixed,
+                top_out: TopOut::ClearBoard,
+                ..GameConfig::default()
+            },
+            GameMode::Classic => GameConfig {
+                randomizer: Randomizer::Nes,
+                scoring: Scoring::Nes,
+                gravity: GravityCurve::Nes,
+                // moving on the ground does not buy time
+                lock_reset: LockReset::Never,
+                next_pieces: 1,
+                ..GameConfig::default()
+            },
+            GameMode::Sega => GameConfig {
+                // no bag, the same piece can come again and again
+                randomizer: Randomizer::Random,
+                scoring: Scoring::Sega,
+                lock_reset: LockReset::Step,
+                next_pieces: 1,
+                ..GameConfig::default()
+            },
+            GameMode::Master => GameConfig {
+                randomizer: Randomizer::Tgm,
+                scoring: Scoring::Tgm,
+                gravity: GravityCurve::Tgm,
+                start_level: 0,
+                level_progression: LevelProgression::Sections,
+                lock_reset: LockReset::Step,
+                next_pieces: 1,
+                ..GameConfig::default()
+            },
+        }
+    }
+}
+
+impl Display for GameMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameMode::Marathon => write!(f, "MARATHON"),
+            GameMode::Classic => write!(f, "CLASSIC"),
+            GameMode::Sega => write!(f, "SEGA"),
+            // the goal is shown apart, it can be changed
+            GameMode::Sprint => write!(f, "SPRINT"),
+            GameMode::Ultra => write!(f, "ULTRA"),
+            GameMode::Dig => write!(f, "DIG"),
+            GameMode::Zen => write!(f, "ZEN"),
+            GameMode::Master => write!(f, "MASTER"),
+            GameMode::Invisible => write!(f, "INVISIBLE"),
+            GameMode::Fading => write!(f, "FADING"),
+            GameMode::Puzzle => write!(f, "PUZZLE"),
+            GameMode::Cascade => write!(f, "CASCADE"),
+            GameMode::Big => write!(f, "BIG"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Game;
+
+    #[test]
+    fn modes_start_on_their_smallest_board() {
+        for mode in GameMode::ALL {
+            let board_size = mode.min_board_size();
+            let game = Game::new(GameConfig {
+                board_size,
+                ..mode.config(&ModeSettings::default())
+            });
+            assert_eq!(game.end(), None, "{}", mode);
+            assert_eq!(game.board_size(), board_size, "{}", mode);
+        }
+    }
+}
@@ -5,8 +5,9 @@ use bevy::{
 use leafwing_input_manager::prelude::ActionState;
 
 use crate::{
+    board::BoardSize,
     player::{Action, Player},
-    GameState, WINDOW_HEIGHT, WINDOW_WIDTH,
+    window_size, GameState,
 };
 
 #[derive(Component)]
@@ -17,6 +18,7 @@ pub fn enter_pause(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     asset_server: Res<AssetServer>,
+    board_size: Res<BoardSize>,
 ) {
     let font = asset_server.load("FiraCode-Regular.ttf");
     let text_style = TextStyle {
@@ -36,12 +38,27 @@ pub fn enter_pause(
         })
         .insert(Pause);
 
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                "R: retry\nENTER: main menu",
+                TextStyle {
+                    font_size: 20.0,
+                    ..text_style
+                },
+            )
+            .with_alignment(text_alignment),
+            transform: Transform::from_translation(Vec3::new(0., -100., 10.)),
+            ..default()
+        })
+        .insert(Pause);
+
     commands
         .spawn_bundle(MaterialMesh2dBundle {
             mesh: meshes
                 .add(
                     Quad {
-                        size: Vec2::new(WINDOW_WIDTH, WINDOW_HEIGHT),
+                        size: window_size(*board_size),
                         ..Default::default()
                     }
                     .into(),
@@ -67,9 +84,20 @@ pub fn pause(
     let input = input_query.single();
     if input.just_pressed(Action::Pause) {
         match state.as_ref().current() {
-            GameState::GameOver => (),
-            GameState::InGame => state.set(GameState::Pause).unwrap(),
-            GameState::Pause => state.set(GameState::InGame).unwrap(),
+            GameState::MainMenu
+            | GameState::HighScores
+            | GameState::NameEntry
+            | GameState::GameOver
+            | GameState::Won
+            | GameState::TimeUp => (),
+            // the game is resumed, not entered again. Retrying or leaving in
+            // the same frame may have queued its state first.
+            GameState::InGame => {
+                let _ = state.push(GameState::Pause);
+            }
+            GameState::Pause => {
+                let _ = state.pop();
+            }
         }
     }
 }
@@ -1,5 +1,3 @@
-use std::ops::Add;
-
 use bevy::prelude::*;
 use rand::{
     distributions::{Distribution, Standard},
@@ -7,12 +5,15 @@ use rand::{
 };
 
 use crate::{
-    board::{BoardPosition, BOARD_HEIGHT, BOARD_WIDTH},
+    board::{BoardPosition, BoardSize},
+    engine::{Game, GameEvent},
+    next_box_left, piece_box_top,
+    settings::Settings,
     square::{spawn_square, Square},
-    SpawnPieceEvent,
+    HOLD_BOX_LEFT,
 };
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PieceType {
     Square,
     T,
@@ -23,17 +24,19 @@ pub enum PieceType {
     InvS,
 }
 
-type PiecePositions = [(i32, i32); 4];
-
-impl Add<BoardPosition> for PiecePositions {
-    type Output = Self;
-
-    fn add(self, rhs: BoardPosition) -> Self::Output {
-        [self[0] + rhs, self[1] + rhs, self[2] + rhs, self[3] + rhs]
-    }
-}
+pub type PiecePositions = [(i32, i32); 4];
 
 impl PieceType {
+    pub const ALL: [PieceType; 7] = [
+        PieceType::Square,
+        PieceType::T,
+        PieceType::L,
+        PieceType::InvL,
+        PieceType::S,
+        PieceType::InvS,
+        PieceType::Bar,
+    ];
+
     fn color(&self) -> Color {
         match self {
             PieceType::Square => Color::RED,
@@ -46,59 +49,82 @@ impl PieceType {
         }
     }
 
-    pub fn anchor(&self) -> BoardPosition {
-        match self {
-            PieceType::Square => BoardPosition::new(0, 0),
-            PieceType::T => BoardPosition::new(-1, 0),
-            PieceType::L => BoardPosition::new(-1, 0),
-            PieceType::InvL => BoardPosition::new(-1, 0),
-            PieceType::Bar => BoardPosition::new(0, 0),
-            PieceType::S => BoardPosition::new(0, 0),
-            PieceType::InvS => BoardPosition::new(0, 0),
-        }
-    }
-
+    /// Positions of the squares relative to the rotation center of the piece
+    ///
+    /// Orientations follow the Super Rotation System: Up is the spawn state,
+    /// Right, Bottom and Left are reached by rotating clockwise.
     pub fn square_pos(&self, orientation: Orientation) -> PiecePositions {
         match self {
-            PieceType::Square => [(0, 0), (1, 0), (0, -1), (1, -1)],
+            // the square does not rotate
+            PieceType::Square => [(0, 0), (1, 0), (0, 1), (1, 1)],
             PieceType::T => match orientation {
-                Orientation::Up => [(1, 0), (0, -1), (1, -1), (2, -1)],
-                Orientation::Left => [(0, -1), (1, 0), (1, -1), (1, -2)],
-                Orientation::Bottom => [(0, -1), (1, -1), (2, -1), (1, -2)],
-                Orientation::Right => [(1, 0), (1, -1), (1, -2), (2, -1)],
+                Orientation::Up => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+                Orientation::Right => [(0, 1), (0, 0), (0, -1), (1, 0)],
+                Orientation::Bottom => [(-1, 0), (0, 0), (1, 0), (0, -1)],
+                Orientation::Left => [(0, 1), (0, 0), (0, -1), (-1, 0)],
             },
             PieceType::L => match orientation {
-                Orientation::Up => [(0, 0), (1, 0), (2, 0), (2, -1)],
-                Orientation::Left => [(0, 0), (0, -1), (0, -2), (1, 0)],
-                Orientation::Bottom => [(0, 0), (0, -1), (1, -1), (2, -1)],
-                Orientation::Right => [(1, 0), (1, -1), (1, -2), (0, -2)],
+                Orientation::Up => [(-1, 1), (-1, 0), (0, 0), (1, 0)],
+                Orientation::Right => [(0, 1), (1, 1), (0, 0), (0, -1)],
+                Orientation::Bottom => [(-1, 0), (0, 0), (1, 0), (1, -1)],
+                Orientation::Left => [(0, 1), (0, 0), (-1, -1), (0, -1)],
             },
-
             PieceType::InvL => match orientation {
-                Orientation::Up => [(0, 0), (1, 0), (2, 0), (0, -1)],
-                Orientation::Left => [(0, 0), (0, -1), (0, -2), (1, -2)],
-                Orientation::Bottom => [(2, 0), (0, -1), (1, -1), (2, -1)],
-                Orientation::Right => [(0, 0), (1, 0), (1, -1), (1, -2)],
+                Orientation::Up => [(1, 1), (-1, 0), (0, 0), (1, 0)],
+                Orientation::Right => [(0, 1), (0, 0), (0, -1), (1, -1)],
+                Orientation::Bottom => [(-1, 0), (0, 0), (1, 0), (-1, -1)],
+                Orientation::Left => [(-1, 1), (0, 1), (0, 0), (0, -1)],
             },
+            // the bar rotates around the center of a 4x4 box, it occupies
+            // the second or third row/column of the box
             PieceType::Bar => match orientation {
-                // horizontal
-                Orientation::Up | Orientation::Bottom => [(-1, 0), (0, 0), (1, 0), (2, 0)],
-                // vertical
-                Orientation::Left | Orientation::Right => [(1, 0), (1, 1), (1, 2), (1, 3)],
+                Orientation::Up => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+                Orientation::Right => [(1, 1), (1, 0), (1, -1), (1, -2)],
+                Orientation::Bottom => [(-1, -1), (0, -1), (1, -1), (2, -1)],
+                Orientation::Left => [(0, 1), (0, 0), (0, -1), (0, -2)],
             },
             PieceType::S => match orientation {
-                Orientation::Up | Orientation::Bottom => [(1, 0), (2, 0), (0, -1), (1, -1)],
-                Orientation::Left | Orientation::Right => [(0, 0), (0, -1), (1, -1), (1, -2)],
+                Orientation::Up => [(0, 1), (1, 1), (-1, 0), (0, 0)],
+                Orientation::Right => [(0, 1), (0, 0), (1, 0), (1, -1)],
+                Orientation::Bottom => [(0, 0), (1, 0), (-1, -1), (0, -1)],
+                Orientation::Left => [(-1, 1), (-1, 0), (0, 0), (0, -1)],
             },
             PieceType::InvS => match orientation {
-                Orientation::Up | Orientation::Bottom => [(0, 0), (1, 0), (1, -1), (2, -1)],
-                Orientation::Left | Orientation::Right => [(0, -1), (0, -2), (1, 0), (1, -1)],
+                Orientation::Up => [(-1, 1), (0, 1), (0, 0), (1, 0)],
+                Orientation::Right => [(1, 1), (0, 0), (1, 0), (0, -1)],
+                Orientation::Bottom => [(-1, 0), (0, 0), (0, -1), (1, -1)],
+                Orientation::Left => [(0, 1), (-1, 0), (0, 0), (-1, -1)],
+            },
+        }
+    }
+
+    /// Offsets to try, in order, when rotating from one orientation to another
+    /// (SRS wall kicks)
+    ///
+    /// The first offset where the rotated piece fits is used.
+    pub fn kicks(&self, from: Orientation, to: Orientation) -> &'static [(i32, i32)] {
+        use Orientation::*;
+        match self {
+            PieceType::Square => &[(0, 0)],
+            PieceType::Bar => match (from, to) {
+                (Up, Right) | (Left, Bottom) => &[(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+                (Right, Up) | (Bottom, Left) => &[(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+                (Right, Bottom) | (Up, Left) => &[(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+                (Bottom, Right) | (Left, Up) => &[(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+                _ => &[(0, 0)],
+            },
+            _ => match (from, to) {
+                (Up, Right) | (Bottom, Right) => &[(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+                (Right, Up) | (Right, Bottom) => &[(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+                (Bottom, Left) | (Up, Left) => &[(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+                (Left, Bottom) | (Left, Up) => &[(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+                _ => &[(0, 0)],
             },
         }
     }
 }
 
-// this made selecting a random piece easy using rand::random()
+// this made selecting a random piece easy using rng.gen()
 impl Distribution<PieceType> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> PieceType {
         match rng.gen_range(0..7) {
@@ -114,7 +140,7 @@ impl Distribution<PieceType> for Standard {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Orientation {
     Up,
     Left,
@@ -149,93 +175,332 @@ impl Orientation {
 #[derive(Component, Clone, Copy)]
 pub struct PieceSquare;
 
-/// Marker components for squares that belong to the current moving piece
+/// Marker components for squares that are displayed in the next piece box
+#[derive(Component, Clone, Copy)]
+pub struct NextPieceSquare;
+
+/// Marker components for squares that are displayed in the hold piece box
+#[derive(Component, Clone, Copy)]
+pub struct HoldPieceSquare;
+
+/// Marker components for squares that show where the current piece will land
 #[derive(Component, Clone, Copy)]
-pub struct NextPieceSquare(PieceType);
+pub struct GhostSquare;
 
 /// The actual moving piece that goes down and can be moved/rotated
-#[derive(Component, Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct Piece {
     pub piece_type: PieceType,
     pub orientation: Orientation,
     /// helper position to ease rotation computation
     pub position: BoardPosition,
+    /// Each square of a big piece is a block of 2x2 squares
+    pub big: bool,
 }
 
+#[derive(Clone, Copy, Debug)]
 pub enum Rotation {
     Clock,
     Anti,
 }
 
 impl Piece {
-    fn square_pos(&self) -> PiecePositions {
-        self.piece_type.square_pos(self.orientation) + self.piece_type.anchor()
+    /// A piece ready to enter a board of the given size from the top
+    pub fn spawn(piece_type: PieceType, big: bool, board_size: BoardSize) -> Self {
+        // big pieces are two squares higher, they enter lower to stay on the board
+        let top = if big {
+            board_size.height - 3
+        } else {
+            board_size.height - 1
+        };
+        Self {
+            piece_type,
+            orientation: Orientation::Up,
+            position: BoardPosition::new(board_size.width / 2, top),
+            big,
+        }
+    }
+
+    /// Size of the blocks the piece is made of, in squares
+    pub fn scale(&self) -> i32 {
+        if self.big {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Position of the block at `offset` blocks from the rotation center
+    pub fn block(&self, (dx, dy): (i32, i32)) -> BoardPosition {
+        self.position + (dx * self.scale(), dy * self.scale())
+    }
+
+    /// Positions of the squares of this piece on the board
+    pub fn positions(&self) -> Vec<(i32, i32)> {
+        let scale = self.scale();
+        self.piece_type
+            .square_pos(self.orientation)
+            .into_iter()
+            .flat_map(|offset| {
+                let block = self.block(offset);
+                (0..scale * scale).map(move |i| (block.x + i % scale, block.y + i / scale))
+            })
+            .collect()
+    }
+}
+
+/// A piece displayed in a box, `index` is its rank from the top of the box
+fn boxed_piece(piece_type: PieceType, box_left: i32, index: i32, board_size: BoardSize) -> Piece {
+    Piece {
+        piece_type,
+        orientation: Orientation::Up,
+        position: BoardPosition::new(box_left + 2, piece_box_top(board_size) - 3 - 3 * index),
+        big: false,
     }
 }
 
-fn spawn_next_random_piece(
+fn spawn_piece_squares<T: Component + Copy>(
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<ColorMaterial>>,
+    piece: &Piece,
+    marker: Option<T>,
 ) {
-    let piece = Piece {
-        piece_type: rand::random(),
-        orientation: Orientation::Up,
-        position: BoardPosition::new(BOARD_WIDTH + 5, 3),
-    };
-    // spawn the squares
-    for square_pos in piece.square_pos() {
+    for square_pos in piece.positions() {
         spawn_square(
             commands,
             meshes,
             materials,
-            piece.position + square_pos,
+            square_pos.into(),
             piece.piece_type.color(),
             Square,
-            Some(NextPieceSquare(piece.piece_type)),
+            marker,
         );
     }
 }
 
-pub fn spawn_next_piece(
+fn move_piece_squares(
+    query: &mut Query<(Entity, &mut BoardPosition), With<PieceSquare>>,
+    piece: &Piece,
+) {
+    // by construction there is a moving square for each square of the piece
+    for ((_, mut bp), pos) in query.iter_mut().zip(piece.positions()) {
+        *bp = pos.into();
+    }
+}
+
+/// Keeps the squares of the current piece in sync with the game
+///
+/// Events are replayed in order: a piece may be spawned, moved and
+/// locked within the same frame.
+pub fn render_piece(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
-    next_query: Query<(Entity, &NextPieceSquare)>,
-    mut event_reader: EventReader<SpawnPieceEvent>,
+    mut event_reader: EventReader<GameEvent>,
+    mut moving_query: Query<(Entity, &mut BoardPosition), With<PieceSquare>>,
 ) {
-    for _ev in event_reader.iter() {
-        let piece_type = next_query
-            .iter()
-            .map(|(_, piece)| piece.0)
-            .nth(0)
-            .unwrap_or_else(|| rand::random());
-
-        let piece = Piece {
-            piece_type,
-            orientation: Orientation::Up,
-            position: BoardPosition::new(BOARD_WIDTH / 2, BOARD_HEIGHT),
-        };
-        // spawn the squares
-        for square_pos in piece.square_pos() {
+    // piece spawned this frame, its squares do not exist yet
+    let mut spawned: Option<Piece> = None;
+    // do the PieceSquare entities still belong to the current piece?
+    let mut moving = true;
+    for event in event_reader.iter() {
+        match event {
+            GameEvent::PieceSpawned(piece) => spawned = Some(*piece),
+            GameEvent::PieceMoved(piece) => match spawned.as_mut() {
+                Some(spawned) => *spawned = *piece,
+                None if moving => move_piece_squares(&mut moving_query, piece),
+                None => (),
+            },
+            GameEvent::PieceLocked(piece) => match spawned.take() {
+                Some(_) => spawn_piece_squares(
+                    &mut commands,
+                    &mut meshes,
+                    &mut materials,
+                    piece,
+                    None::<PieceSquare>,
+                ),
+                None => {
+                    move_piece_squares(&mut moving_query, piece);
+                    // squares are left on the board, only the marker is removed
+                    for (entity, _) in &moving_query {
+                        commands.entity(entity).remove::<PieceSquare>();
+                    }
+                    moving = false;
+                }
+            },
+            GameEvent::PieceHeld(_) => match spawned.take() {
+                // its squares were never spawned
+                Some(_) => (),
+                None if moving => {
+                    for (entity, _) in &moving_query {
+                        commands.entity(entity).despawn_recursive();
+                    }
+                    moving = false;
+                }
+                None => (),
+            },
+            _ => (),
+        }
+    }
+    if let Some(piece) = spawned {
+        spawn_piece_squares(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            &piece,
+            Some(PieceSquare),
+        );
+    }
+}
+
+/// Displays the upcoming pieces in the next piece box
+pub fn render_next_piece(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    game: Res<Game>,
+    next_query: Query<Entity, With<NextPieceSquare>>,
+    mut event_reader: EventReader<GameEvent>,
+) {
+    if !event_reader
+        .iter()
+        .any(|event| matches!(event, GameEvent::PieceSpawned(_)))
+    {
+        return;
+    }
+    for entity in &next_query {
+        commands.entity(entity).despawn_recursive();
+    }
+    for (index, piece_type) in game.next_pieces().iter().enumerate() {
+        spawn_piece_squares(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            &boxed_piece(
+                *piece_type,
+                next_box_left(game.board_size()),
+                index as i32,
+                game.board_size(),
+            ),
+            Some(NextPieceSquare),
+        );
+    }
+}
+
+/// Displays the held piece in the hold piece box
+pub fn render_hold_piece(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    game: Res<Game>,
+    hold_query: Query<Entity, With<HoldPieceSquare>>,
+    mut event_reader: EventReader<GameEvent>,
+) {
+    let held_piece = event_reader
+        .iter()
+        .fold(None, |held_piece, event| match event {
+            GameEvent::PieceHeld(piece_type) => Some(*piece_type),
+            _ => held_piece,
+        });
+    if let Some(piece_type) = held_piece {
+        for entity in &hold_query {
+            commands.entity(entity).despawn_recursive();
+        }
+        spawn_piece_squares(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            &boxed_piece(piece_type, HOLD_BOX_LEFT, 0, game.board_size()),
+            Some(HoldPieceSquare),
+        );
+    }
+}
+
+const GHOST_ALPHA: f32 = 0.3;
+
+/// Displays a translucent copy of the current piece where it would land
+pub fn render_ghost_piece(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    game: Res<Game>,
+    settings: Res<Settings>,
+    ghost_query: Query<Entity, With<GhostSquare>>,
+    mut event_reader: EventReader<GameEvent>,
+) {
+    // any event may have changed the current piece or the board
+    if event_reader.iter().count() == 0 && !settings.is_changed() {
+        return;
+    }
+    for entity in &ghost_query {
+        commands.entity(entity).despawn_recursive();
+    }
+    if !settings.ghost_piece {
+        return;
+    }
+    if let Some(ghost) = game.ghost_piece() {
+        let mut color = ghost.piece_type.color();
+        color.set_a(GHOST_ALPHA);
+        for square_pos in ghost.positions() {
             spawn_square(
                 &mut commands,
                 &mut meshes,
                 &mut materials,
-                piece.position + square_pos,
-                piece.piece_type.color(),
+                square_pos.into(),
+                color,
                 Square,
-                Some(PieceSquare),
+                Some(GhostSquare),
             );
         }
-        // spawn the actual piece
-        commands.spawn().insert(piece);
+    }
+}
 
-        for (entity, _) in &next_query {
-            commands.entity(entity).despawn_recursive();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIENTATIONS: [Orientation; 4] = [
+        Orientation::Up,
+        Orientation::Right,
+        Orientation::Bottom,
+        Orientation::Left,
+    ];
+
+    #[test]
+    fn kicks_start_in_place() {
+        for piece_type in PieceType::ALL {
+            for from in ORIENTATIONS {
+                for rotation in [Rotation::Clock, Rotation::Anti] {
+                    let to = from.apply_rotation(rotation);
+                    assert_eq!(piece_type.kicks(from, to)[0], (0, 0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rotating_back_kicks_the_other_way() {
+        for piece_type in PieceType::ALL {
+            for from in ORIENTATIONS {
+                let to = from.apply_rotation(Rotation::Clock);
+                let kicks = piece_type.kicks(from, to);
+                let back_kicks = piece_type.kicks(to, from);
+                assert_eq!(kicks.len(), back_kicks.len());
+                for ((dx, dy), back_kick) in kicks.iter().zip(back_kicks) {
+                    assert_eq!((-dx, -dy), *back_kick, "{:?} {:?}", piece_type, from);
+                }
+            }
         }
+    }
 
-        // spawn the next piece
-        spawn_next_random_piece(&mut commands, &mut meshes, &mut materials)
+    #[test]
+    fn bar_and_others_have_five_kicks() {
+        for from in ORIENTATIONS {
+            let to = from.apply_rotation(Rotation::Anti);
+            assert_eq!(PieceType::Bar.kicks(from, to).len(), 5);
+            assert_eq!(PieceType::T.kicks(from, to).len(), 5);
+            assert_eq!(PieceType::Square.kicks(from, to), &[(0, 0)]);
+        }
     }
 }
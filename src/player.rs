@@ -7,9 +7,15 @@ pub enum Action {
     Left,
     Right,
     Down,
+    HardDrop,
     RotateClock,
     RotateAnti,
+    Hold,
     Pause,
+    Retry,
+    // Menus
+    Up,
+    Select,
 }
 #[derive(Component)]
 pub struct Player;
@@ -33,6 +39,9 @@ impl PlayerBundle {
         input_map.insert(KeyCode::Down, Down);
         input_map.insert(GamepadButtonType::DPadDown, Down);
 
+        input_map.insert(KeyCode::Space, HardDrop);
+        input_map.insert(GamepadButtonType::DPadUp, HardDrop);
+
         input_map.insert(KeyCode::Left, Left);
         input_map.insert(GamepadButtonType::DPadLeft, Left);
 
@@ -45,9 +54,23 @@ impl PlayerBundle {
         input_map.insert(KeyCode::RShift, RotateAnti);
         input_map.insert(GamepadButtonType::C, RotateAnti);
 
+        input_map.insert(KeyCode::C, Hold);
+        input_map.insert(GamepadButtonType::LeftTrigger, Hold);
+
         input_map.insert(KeyCode::P, Pause);
         input_map.insert(GamepadButtonType::Start, Pause);
 
+        input_map.insert(KeyCode::R, Retry);
+        input_map.insert(GamepadButtonType::North, Retry);
+
+        // Menus, down, left and right are shared with the movement
+
+        input_map.insert(KeyCode::Up, Up);
+        input_map.insert(GamepadButtonType::DPadUp, Up);
+
+        input_map.insert(KeyCode::Return, Select);
+        input_map.insert(GamepadButtonType::South, Select);
+
         input_map
     }
 }
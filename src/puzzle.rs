@@ -0,0 +1,387 @@
+//! Puzzles: a board to start from, the pieces to play and a goal to reach.
+//!
+//! Each puzzle is a text file of the `assets/puzzles` folder:
+//!
+//! ```text
+//! # lines starting with a # are ignored
+//! name: FIRST T-SPIN
+//! goal: t-spin double
+//! pieces: T O I
+//! board:
+//! XX...XXXXX
+//! X...XXXXXX
+//! XX.XXXXXXX
+//! ```
+//!
+//! The goal is `lines <n>`, `perfect clear` or the name of a clear as
+//! announced in the game. Pieces are given by their letter (I, O, T, L, J, S
+//! and Z) and the board rows from top to bottom, with `X` for a square and
+//! `.` for an empty cell: the last row is the bottom of the board. The puzzle
+//! is played on a board as wide as its rows.
+
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    board::{BoardPosition, BoardSize},
+    engine::{ClearKind, GameEvent},
+    piece::PieceType,
+    square::{spawn_square, Square},
+};
+
+const PUZZLE_COLOR: Color = Color::SILVER;
+
+/// The clears a puzzle can ask for
+const CLEAR_KINDS: [ClearKind; 11] = [
+    ClearKind::Single,
+    ClearKind::Double,
+    ClearKind::Triple,
+    ClearKind::Tetris,
+    ClearKind::MiniTSpin,
+    ClearKind::MiniTSpinSingle,
+    ClearKind::MiniTSpinDouble,
+    ClearKind::TSpin,
+    ClearKind::TSpinSingle,
+    ClearKind::TSpinDouble,
+    ClearKind::TSpinTriple,
+];
+
+/// What must be done to solve a puzzle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PuzzleGoal {
+    /// Clearing this many lines
+    Lines(usize),
+    /// Leaving the board empty after a line clear
+    PerfectClear,
+    /// Locking a piece making this clear
+    Clear(ClearKind),
+}
+
+impl PuzzleGoal {
+    /// Is the goal reached after locking a piece?
+    pub fn is_reached(
+        &self,
+        lines: usize,
+        clear_kind: Option<ClearKind>,
+        perfect_clear: bool,
+    ) -> bool {
+        match self {
+            PuzzleGoal::Lines(goal) => lines >= *goal,
+            PuzzleGoal::PerfectClear => perfect_clear,
+            PuzzleGoal::Clear(goal) => clear_kind == Some(*goal),
+        }
+    }
+
+    fn parse(goal: &str) -> Option<Self> {
+        let goal = goal.trim().to_uppercase();
+        if goal == "PERFECT CLEAR" {
+            return Some(PuzzleGoal::PerfectClear);
+        }
+        if let Some(lines) = goal.strip_prefix("LINES ") {
+            return lines.trim().parse().ok().map(PuzzleGoal::Lines);
+        }
+        CLEAR_KINDS
+            .into_iter()
+            .find(|clear_kind| clear_kind.to_string().replace('\n', " ") == goal)
+            .map(PuzzleGoal::Clear)
+    }
+}
+
+impl Display for PuzzleGoal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PuzzleGoal::Lines(lines) => write!(f, "{} LINES", lines),
+            PuzzleGoal::PerfectClear => write!(f, "PERFECT\nCLEAR"),
+            PuzzleGoal::Clear(clear_kind) => write!(f, "{}", clear_kind),
+        }
+    }
+}
+
+/// A board to start from, the pieces to play and a goal to reach
+#[derive(Clone, Debug)]
+pub struct Puzzle {
+    pub name: String,
+    /// Squares on the board when the puzzle starts
+    pub board: Vec<BoardPosition>,
+    pub board_size: BoardSize,
+    /// Every piece that can be played, in order
+    pub pieces: Vec<PieceType>,
+    pub goal: PuzzleGoal,
+}
+
+impl Puzzle {
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut name = None;
+        let mut goal = None;
+        let mut pieces = None;
+        let mut lines = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        for line in lines.by_ref() {
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| format!("expected `key: value`, found {:?}", line))?;
+            match key.trim() {
+                "name" => name = Some(value.trim().to_uppercase()),
+                "goal" => {
+                    goal = Some(
+                        PuzzleGoal::parse(value)
+                            .ok_or_else(|| format!("unknown goal {:?}", value.trim()))?,
+                    )
+                }
+                "pieces" => {
+                    pieces = Some(
+                        value
+                            .split_whitespace()
+                            .flat_map(str::chars)
+                            .map(piece_from_letter)
+                            .collect::<Result<Vec<_>, _>>()?,
+                    )
+                }
+                // the rows of the board come last
+                "board" => break,
+                key => return Err(format!("unknown key {:?}", key)),
+            }
+        }
+        let rows: Vec<&str> = lines.collect();
+        let mut board_size = BoardSize::STANDARD;
+        if rows.len() > board_size.height as usize {
+            return Err(format!("more than {} rows", board_size.height));
+        }
+        if let Some(row) = rows.first() {
+            board_size.width = row.chars().count() as i32;
+            if board_size.width < BoardSize::MIN.width || board_size.width > BoardSize::MAX.width {
+                return Err(format!(
+                    "rows must be {} to {} wide",
+                    BoardSize::MIN.width,
+                    BoardSize::MAX.width
+                ));
+            }
+        }
+        let mut board = Vec::new();
+        // the last row is the bottom of the board
+        for (y, row) in (1..).zip(rows.iter().rev()) {
+            if row.chars().count() != board_size.width as usize {
+                return Err(format!("row {:?} is not {} wide", row, board_size.width));
+            }
+            for (x, cell) in (1..).zip(row.chars()) {
+                match cell {
+                    'X' => board.push(BoardPosition::new(x, y)),
+                    '.' => (),
+                    _ => return Err(format!("unknown cell {:?}", cell)),
+                }
+            }
+        }
+        let pieces = pieces.ok_or("missing pieces")?;
+        if pieces.is_empty() {
+            return Err("no piece to play".to_string());
+        }
+        Ok(Self {
+            name: name.ok_or("missing name")?,
+            board,
+            board_size,
+            pieces,
+            goal: goal.ok_or("missing goal")?,
+        })
+    }
+}
+
+fn piece_from_letter(letter: char) -> Result<PieceType, String> {
+    match letter.to_ascii_uppercase() {
+        'I' => Ok(PieceType::Bar),
+        'O' => Ok(PieceType::Square),
+        'T' => Ok(PieceType::T),
+        // the L type of the game is the J shape
+        'J' => Ok(PieceType::L),
+        'L' => Ok(PieceType::InvL),
+        'S' => Ok(PieceType::S),
+        'Z' => Ok(PieceType::InvS),
+        _ => Err(format!("unknown piece {:?}", letter)),
+    }
+}
+
+/// Every puzzle found in the puzzle folder, in file name order
+#[derive(Default)]
+pub struct Puzzles(pub Vec<Puzzle>);
+
+impl Puzzles {
+    /// Reads the puzzle files, the invalid ones are left out
+    pub fn load() -> Self {
+        let dir = puzzles_dir();
+        let mut paths: Vec<PathBuf> = match fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+                .collect(),
+            Err(e) => {
+                eprintln!("Cannot read puzzles from {}: {}", dir.display(), e);
+                return Self::default();
+            }
+        };
+        paths.sort();
+        let puzzles = paths
+            .iter()
+            .filter_map(|path| match load_puzzle(path) {
+                Ok(puzzle) => Some(puzzle),
+                Err(e) => {
+                    eprintln!("Ignoring puzzle {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .collect();
+        Self(puzzles)
+    }
+}
+
+fn load_puzzle(path: &Path) -> Result<Puzzle, String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    Puzzle::parse(&content)
+}
+
+/// The puzzles are with the other assets, found like Bevy does
+fn puzzles_dir() -> PathBuf {
+    let root = std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::current_exe()
+                .ok()
+                .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        })
+        .unwrap_or_default();
+    root.join("assets").join("puzzles")
+}
+
+/// Spawns the squares the puzzle starts with
+pub fn render_puzzle(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut event_reader: EventReader<GameEvent>,
+) {
+    for event in event_reader.iter() {
+        if let GameEvent::PuzzleLoaded(positions) = event {
+            for position in positions {
+                spawn_square(
+                    &mut commands,
+                    &mut meshes,
+                    &mut materials,
+                    *position,
+                    PUZZLE_COLOR,
+                    Square,
+                    None::<Square>,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::Orientation;
+
+    const PUZZLE: &str = "
+        # a comment
+        name: Test
+        goal: t-spin double
+        pieces: I O T JLSZ
+        board:
+        X...X
+        XX.XX
+    ";
+
+    #[test]
+    fn parse() {
+        let puzzle = Puzzle::parse(PUZZLE).unwrap();
+        assert_eq!(puzzle.name, "TEST");
+        assert_eq!(puzzle.goal, PuzzleGoal::Clear(ClearKind::TSpinDouble));
+        assert_eq!(
+            puzzle.pieces,
+            [
+                PieceType::Bar,
+                PieceType::Square,
+                PieceType::T,
+                PieceType::L,
+                PieceType::InvL,
+                PieceType::S,
+                PieceType::InvS,
+            ]
+        );
+        // the board is as wide as the rows, the last row at the bottom
+        assert_eq!(
+            puzzle.board_size,
+            BoardSize::new(5, BoardSize::STANDARD.height)
+        );
+        assert_eq!(
+            puzzle.board,
+            [(1, 1), (2, 1), (4, 1), (5, 1), (1, 2), (5, 2)].map(BoardPosition::from)
+        );
+    }
+
+    #[test]
+    fn letters_are_the_shapes() {
+        // J has its corner on the left, L on the right
+        let shapes = |letter| {
+            let piece_type = piece_from_letter(letter).unwrap();
+            piece_type.square_pos(Orientation::Up)
+        };
+        assert!(shapes('J').contains(&(-1, 1)));
+        assert!(shapes('L').contains(&(1, 1)));
+    }
+
+    #[test]
+    fn goals() {
+        assert_eq!(PuzzleGoal::parse("lines 3"), Some(PuzzleGoal::Lines(3)));
+        assert_eq!(
+            PuzzleGoal::parse("Perfect Clear"),
+            Some(PuzzleGoal::PerfectClear)
+        );
+        assert_eq!(
+            PuzzleGoal::parse("mini t-spin single"),
+            Some(PuzzleGoal::Clear(ClearKind::MiniTSpinSingle))
+        );
+        assert_eq!(PuzzleGoal::parse("lines"), None);
+        assert_eq!(PuzzleGoal::parse("quadruple"), None);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |content: &str| Puzzle::parse(content).unwrap_err();
+        let board_error = |rows: &str| {
+            error(&format!(
+                "name: a\ngoal: tetris\npieces: I\nboard:\n{}",
+                rows
+            ))
+        };
+        assert!(error("name: a\ngoal: tetris\npieces: Q\n").contains("unknown piece"));
+        assert!(error("name: a\ngoal: tetris\npieces:\n").contains("no piece"));
+        assert!(error("name: a\ngoal: win\npieces: I\n").contains("unknown goal"));
+        assert!(error("name: a\npieces: I\n").contains("missing goal"));
+        assert!(error("name a\n").contains("key: value"));
+        assert!(board_error("....\n...\n").contains("not 4 wide"));
+        assert!(board_error("...\n").contains("wide"));
+        assert!(board_error("..o.\n").contains("unknown cell"));
+        let rows = "....\n".repeat(BoardSize::STANDARD.height as usize + 1);
+        assert!(board_error(&rows).contains("more than"));
+    }
+
+    #[test]
+    fn shipped_puzzles_are_valid() {
+        let paths: Vec<PathBuf> = fs::read_dir(puzzles_dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert!(!paths.is_empty());
+        for path in paths {
+            if let Err(e) = load_puzzle(&path) {
+                panic!("{}: {}", path.display(), e);
+            }
+        }
+    }
+}
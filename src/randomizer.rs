@@ -0,0 +1,279 @@
+//! How the sequence of pieces of a game is chosen.
+
+use std::{collections::VecDeque, fmt::Display, str::FromStr};
+
+use rand::{seq::SliceRandom, Rng, RngCore};
+
+use crate::piece::PieceType;
+
+/// Picks the pieces a game is played with
+pub trait PieceGenerator: Send + Sync {
+    /// `None` once a limited sequence of pieces is over
+    fn next_piece(&mut self, rng: &mut dyn RngCore) -> Option<PieceType>;
+}
+
+/// The available piece generators
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Randomizer {
+    /// Each piece is picked at random, the same piece can come again and again
+    Random,
+    /// The 7 pieces are dealt in a random order, then again...
+    #[default]
+    Bag7,
+    /// Same as `Bag7` with two of each piece in the bag
+    Bag14,
+    /// The NES rerolls once when the same piece comes twice in a row
+    Nes,
+    /// TGM avoids the last 4 pieces, rolling up to 6 times
+    Tgm,
+}
+
+impl Randomizer {
+    pub const ALL: [Randomizer; 5] = [
+        Randomizer::Random,
+        Randomizer::Bag7,
+        Randomizer::Bag14,
+        Randomizer::Nes,
+        Randomizer::Tgm,
+    ];
+
+    pub fn generator(&self) -> Box<dyn PieceGenerator> {
+        match self {
+            Randomizer::Random => Box::new(RandomGenerator),
+            Randomizer::Bag7 => Box::new(BagGenerator::new(1)),
+            Randomizer::Bag14 => Box::new(BagGenerator::new(2)),
+            Randomizer::Nes => Box::new(NesGenerator::default()),
+            Randomizer::Tgm => Box::new(TgmGenerator::new(4, 6)),
+        }
+    }
+}
+
+impl Display for Randomizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Randomizer::Random => write!(f, "RANDOM"),
+            Randomizer::Bag7 => write!(f, "BAG 7"),
+            Randomizer::Bag14 => write!(f, "BAG 14"),
+            Randomizer::Nes => write!(f, "NES"),
+            Randomizer::Tgm => write!(f, "TGM"),
+        }
+    }
+}
+
+/// Reads the name of a randomizer: `random`, `bag7`, `bag14`, `nes` or `tgm`
+impl FromStr for Randomizer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "random" => Ok(Randomizer::Random),
+            "bag7" => Ok(Randomizer::Bag7),
+            "bag14" => Ok(Randomizer::Bag14),
+            "nes" => Ok(Randomizer::Nes),
+            "tgm" => Ok(Randomizer::Tgm),
+            _ => Err(format!(
+                "expected random, bag7, bag14, nes or tgm, found {:?}",
+                s
+            )),
+        }
+    }
+}
+
+pub struct RandomGenerator;
+
+impl PieceGenerator for RandomGenerator {
+    fn next_piece(&mut self, rng: &mut dyn RngCore) -> Option<PieceType> {
+        Some(rng.gen())
+    }
+}
+
+/// Deals pieces from a shuffled bag holding `copies` of each piece
+pub struct BagGenerator {
+    copies: usize,
+    bag: Vec<PieceType>,
+}
+
+impl BagGenerator {
+    pub fn new(copies: usize) -> Self {
+        Self {
+            copies,
+            bag: Vec::with_capacity(copies * PieceType::ALL.len()),
+        }
+    }
+}
+
+impl PieceGenerator for BagGenerator {
+    fn next_piece(&mut self, rng: &mut dyn RngCore) -> Option<PieceType> {
+        if self.bag.is_empty() {
+            for _ in 0..self.copies {
+                self.bag.extend_from_slice(&PieceType::ALL);
+            }
+            self.bag.shuffle(rng);
+        }
+        // never `None`: the bag has just been filled
+        self.bag.pop()
+    }
+}
+
+#[derive(Default)]
+pub struct NesGenerator {
+    previous: Option<PieceType>,
+}
+
+impl PieceGenerator for NesGenerator {
+    fn next_piece(&mut self, rng: &mut dyn RngCore) -> Option<PieceType> {
+        // the NES rolls 8 values: the 8th one, like the previous piece,
+        // means "roll again" and the second roll is always kept
+        let roll = rng.gen_range(0..=PieceType::ALL.len());
+        let piece = match PieceType::ALL.get(roll) {
+            Some(piece) if Some(*piece) != self.previous => *piece,
+            _ => rng.gen(),
+        };
+        self.previous = Some(piece);
+        Some(piece)
+    }
+}
+
+/// Rolls up to `rolls` times to find a piece absent from the history
+pub struct TgmGenerator {
+    history: VecDeque<PieceType>,
+    rolls: usize,
+    first: bool,
+}
+
+impl TgmGenerator {
+    pub fn new(history_len: usize, rolls: usize) -> Self {
+        Self {
+            // the history starts full of Z pieces
+            history: vec![PieceType::InvS; history_len].into(),
+            rolls,
+            first: true,
+        }
+    }
+}
+
+impl PieceGenerator for TgmGenerator {
+    fn next_piece(&mut self, rng: &mut dyn RngCore) -> Option<PieceType> {
+        let piece = if self.first {
+            // the first piece is never a S, a Z or a square
+            self.first = false;
+            *[PieceType::Bar, PieceType::L, PieceType::InvL, PieceType::T]
+                .choose(rng)
+                .unwrap()
+        } else {
+            let mut piece = rng.gen();
+            for _ in 1..self.rolls {
+                if !self.history.contains(&piece) {
+                    break;
+                }
+                piece = rng.gen();
+            }
+            piece
+        };
+        self.history.pop_front();
+        self.history.push_back(piece);
+        Some(piece)
+    }
+}
+
+/// Deals the given pieces in order, then no more
+pub struct SequenceGenerator {
+    pieces: VecDeque<PieceType>,
+}
+
+impl SequenceGenerator {
+    pub fn new(pieces: &[PieceType]) -> Self {
+        Self {
+            pieces: pieces.iter().copied().collect(),
+        }
+    }
+}
+
+impl PieceGenerator for SequenceGenerator {
+    fn next_piece(&mut self, _rng: &mut dyn RngCore) -> Option<PieceType> {
+        self.pieces.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    fn pieces(generator: &mut dyn PieceGenerator, seed: u64, count: usize) -> Vec<PieceType> {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        (0..count)
+            .map(|_| generator.next_piece(&mut rng).unwrap())
+            .collect()
+    }
+
+    /// How many pieces are the same as one of the `history_len` previous ones
+    fn repeats(pieces: &[PieceType], history_len: usize) -> usize {
+        (1..pieces.len())
+            .filter(|i| pieces[i.saturating_sub(history_len)..*i].contains(&pieces[*i]))
+            .count()
+    }
+
+    #[test]
+    fn bags_hold_each_piece() {
+        for (randomizer, copies) in [(Randomizer::Bag7, 1), (Randomizer::Bag14, 2)] {
+            let bag_len = copies * PieceType::ALL.len();
+            let pieces = pieces(randomizer.generator().as_mut(), 1, bag_len * 20);
+            for bag in pieces.chunks(bag_len) {
+                for piece_type in PieceType::ALL {
+                    let count = bag.iter().filter(|piece| **piece == piece_type).count();
+                    assert_eq!(count, copies, "{:?}", randomizer);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn random_deals_every_piece() {
+        let pieces = pieces(Randomizer::Random.generator().as_mut(), 1, 100);
+        for piece_type in PieceType::ALL {
+            assert!(pieces.contains(&piece_type));
+        }
+    }
+
+    #[test]
+    fn nes_avoids_the_previous_piece() {
+        let random = pieces(Randomizer::Random.generator().as_mut(), 1, 1000);
+        let nes = pieces(Randomizer::Nes.generator().as_mut(), 1, 1000);
+        // 1 in 7 at random, 1 in 28 on the NES
+        assert!(repeats(&nes, 1) * 2 < repeats(&random, 1));
+    }
+
+    #[test]
+    fn tgm_avoids_its_history() {
+        let random = pieces(Randomizer::Random.generator().as_mut(), 1, 1000);
+        let tgm = pieces(Randomizer::Tgm.generator().as_mut(), 1, 1000);
+        assert!(repeats(&tgm, 4) * 5 < repeats(&random, 4));
+    }
+
+    #[test]
+    fn names_are_read() {
+        assert_eq!("nes".parse(), Ok(Randomizer::Nes));
+        assert_eq!(" Bag14".parse(), Ok(Randomizer::Bag14));
+        assert!("bag".parse::<Randomizer>().is_err());
+    }
+
+    #[test]
+    fn tgm_never_starts_with_s_z_or_square() {
+        for seed in 0..100 {
+            let first = pieces(Randomizer::Tgm.generator().as_mut(), seed, 1)[0];
+            assert!(![PieceType::S, PieceType::InvS, PieceType::Square].contains(&first));
+        }
+    }
+
+    #[test]
+    fn sequence_ends() {
+        let mut generator = SequenceGenerator::new(&[PieceType::T, PieceType::Bar]);
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        assert_eq!(generator.next_piece(&mut rng), Some(PieceType::T));
+        assert_eq!(generator.next_piece(&mut rng), Some(PieceType::Bar));
+        assert_eq!(generator.next_piece(&mut rng), None);
+    }
+}
@@ -3,15 +3,45 @@ use std::{fmt::Display, time::Duration};
 use bevy::prelude::*;
 
 use crate::{
-    board::{BoardPosition, BOARD_HEIGHT, BOARD_WIDTH},
-    MoveDownTimer,
+    board::{BoardPosition, BoardSize},
+    engine::{Game, GameEvent},
+    high_score::format_duration,
+    master::Grade,
+    piece_box_top,
+    puzzle::PuzzleGoal,
+    HOLD_BOX_LEFT,
 };
 
-pub struct LinesCompletedEvent(pub usize);
+/// The texts are displayed below the hold piece box
+const HUD_LEFT: i32 = HOLD_BOX_LEFT;
+
+/// Line of a text of the HUD, from the top of the HUD
+#[derive(Component)]
+pub struct HudLine(i32);
+
+/// Position of a line of the HUD, which follows the top of the board
+fn hud_position(board_size: BoardSize, line: i32) -> BoardPosition {
+    BoardPosition::new(HUD_LEFT, piece_box_top(board_size) - 7 - line)
+}
+
+/// Components placing a text on the given line of the HUD
+fn hud_line(board_size: BoardSize, line: i32) -> (HudLine, BoardPosition) {
+    (HudLine(line), hud_position(board_size, line))
+}
+
+/// Moves the HUD along with the top of the board when its size changes
+pub fn place_hud(board_size: Res<BoardSize>, mut query: Query<(&HudLine, &mut BoardPosition)>) {
+    if !board_size.is_changed() {
+        return;
+    }
+    for (line, mut bp) in &mut query {
+        *bp = hud_position(*board_size, line.0);
+    }
+}
 
 #[derive(Component)]
 pub struct Level {
-    pub level: f64,
+    pub level: usize,
 }
 
 #[derive(Component, Default)]
@@ -21,52 +51,128 @@ pub struct LineCompleted {
 
 impl Default for Level {
     fn default() -> Self {
-        Self { level: 1. }
-    }
-}
-
-impl Level {
-    pub fn get_down_duration(&self) -> Duration {
-        Duration::from_secs_f64(0.5 - 0.05 * self.level)
+        Self { level: 1 }
     }
 }
 
 #[derive(Default, Component)]
 pub struct Score(usize);
 
-pub fn increase_score_and_level(
+#[derive(Default, Component)]
+pub struct Combo(usize);
+
+#[derive(Default, Component)]
+pub struct BackToBack(usize);
+
+#[derive(Default, Component)]
+pub struct PlayTime(Duration);
+
+/// Grade of a master game, nothing is displayed in the other modes
+#[derive(Default, Component)]
+pub struct MasterGrade(Option<Grade>);
+
+/// Goal of a puzzle, in place of the grade which puzzles never have
+#[derive(Default, Component)]
+pub struct Goal(Option<PuzzleGoal>);
+
+/// Text announcing the last lines cleared or T-spin
+#[derive(Component)]
+pub struct ClearAnnouncement {
+    timer: Timer,
+}
+
+/// How long the announcement of a clear stays on screen
+const ANNOUNCEMENT_DURATION: f32 = 1.5;
+
+/// Displays the score, level and lines of the game after a piece
+/// has been locked
+#[allow(clippy::too_many_arguments)]
+pub fn update_score_and_level(
+    game: Res<Game>,
     mut level: Query<&mut Level>,
     mut lines: Query<&mut LineCompleted>,
     mut score: Query<&mut Score>,
-    mut timer: ResMut<MoveDownTimer>,
-    mut event_reader: EventReader<LinesCompletedEvent>,
+    mut combo: Query<&mut Combo>,
+    mut back_to_back: Query<&mut BackToBack>,
+    mut grade: Query<&mut MasterGrade>,
+    mut event_reader: EventReader<GameEvent>,
 ) {
-    for completed in event_reader.iter() {
-        let mut score = score.single_mut();
-        let mut level = level.single_mut();
-        let mut lines = lines.single_mut();
-
-        lines.line_completed += completed.0;
-        let old_level = level.level;
-        level.level = (lines.line_completed / 10) as f64 + 1.;
-        if old_level != level.level {
-            // adjust timer duration
-            timer.timer.set_duration(level.get_down_duration());
+    for event in event_reader.iter() {
+        if let GameEvent::PieceLocked(_) = event {
+            // drops are rewarded even without completing lines
+            score.single_mut().0 = game.score();
+            combo.single_mut().0 = game.combo();
+            back_to_back.single_mut().0 = game.back_to_back();
+            // master levels also go up with each piece
+            level.single_mut().level = game.level();
+            grade.single_mut().0 = game.grade();
         }
-        score.0 += level.level as usize
-            * completed.0
-            * match completed.0 {
-                4 => 10,
-                _ => 7,
+        if let GameEvent::LinesCleared(_) = event {
+            score.single_mut().0 = game.score();
+            level.single_mut().level = game.level();
+            lines.single_mut().line_completed = game.lines();
+        }
+    }
+}
+
+/// Displays the counters of a new game
+#[allow(clippy::too_many_arguments)]
+pub fn reset_score(
+    game: Res<Game>,
+    mut level: Query<&mut Level>,
+    mut lines: Query<&mut LineCompleted>,
+    mut score: Query<&mut Score>,
+    mut combo: Query<&mut Combo>,
+    mut back_to_back: Query<&mut BackToBack>,
+    mut play_time: Query<&mut PlayTime>,
+    mut grade: Query<&mut MasterGrade>,
+    mut goal: Query<&mut Goal>,
+    mut announcement: Query<&mut Text, With<ClearAnnouncement>>,
+) {
+    level.single_mut().level = game.level();
+    lines.single_mut().line_completed = game.lines();
+    score.single_mut().0 = game.score();
+    combo.single_mut().0 = game.combo();
+    back_to_back.single_mut().0 = game.back_to_back();
+    play_time.single_mut().0 = game.remaining().unwrap_or_else(|| game.elapsed());
+    grade.single_mut().0 = game.grade();
+    goal.single_mut().0 = game.puzzle().map(|puzzle| puzzle.goal);
+    announcement.single_mut().sections[0].value.clear();
+}
+
+/// Displays the time elapsed since the start of the game, or the time
+/// left when the game is limited
+pub fn update_play_time(game: Res<Game>, mut play_time: Query<&mut PlayTime>) {
+    play_time.single_mut().0 = game.remaining().unwrap_or_else(|| game.elapsed());
+}
+
+pub fn announce_clear(
+    time: Res<Time>,
+    game: Res<Game>,
+    mut query: Query<(&mut ClearAnnouncement, &mut Text)>,
+    mut event_reader: EventReader<GameEvent>,
+) {
+    let (mut announcement, mut text) = query.single_mut();
+    for event in event_reader.iter() {
+        if let GameEvent::Clear(clear_kind) = event {
+            text.sections[0].value = match game.chain() {
+                chain if chain > 1 => format!("{}\nCHAIN x{}", clear_kind, chain),
+                _ => clear_kind.to_string(),
             };
-        println!(
-            "completed: {}\tlevel: {}\tscore: {}",
-            lines.line_completed, level.level, score.0
-        );
+            announcement.timer.reset();
+        }
+    }
+    announcement.timer.tick(time.delta());
+    if announcement.timer.just_finished() {
+        text.sections[0].value.clear();
     }
 }
 
-pub fn setup_score(mut commands: Commands, asset_server: Res<AssetServer>) {
+pub fn setup_score(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    board_size: Res<BoardSize>,
+) {
     let font = asset_server.load("FiraCode-Regular.ttf");
     let text_style = TextStyle {
         font,
@@ -82,9 +188,9 @@ pub fn setup_score(mut commands: Commands, asset_server: Res<AssetServer>) {
         .spawn_bundle(Text2dBundle {
             text: Text::from_section(initial_score.to_string(), text_style.clone())
                 .with_alignment(text_alignment),
-            transform: BoardPosition::new(BOARD_WIDTH + 2, BOARD_HEIGHT - 4).to_real_position(),
             ..default()
         })
+        .insert_bundle(hud_line(*board_size, 4))
         .insert(initial_score);
 
     let initial_level = Level::default();
@@ -92,9 +198,9 @@ pub fn setup_score(mut commands: Commands, asset_server: Res<AssetServer>) {
         .spawn_bundle(Text2dBundle {
             text: Text::from_section(initial_level.to_string(), text_style.clone())
                 .with_alignment(text_alignment),
-            transform: BoardPosition::new(BOARD_WIDTH + 2, BOARD_HEIGHT - 2).to_real_position(),
             ..default()
         })
+        .insert_bundle(hud_line(*board_size, 2))
         .insert(initial_level);
 
     let initial_lines = LineCompleted::default();
@@ -102,10 +208,70 @@ pub fn setup_score(mut commands: Commands, asset_server: Res<AssetServer>) {
         .spawn_bundle(Text2dBundle {
             text: Text::from_section(initial_lines.to_string(), text_style.clone())
                 .with_alignment(text_alignment),
-            transform: BoardPosition::new(BOARD_WIDTH + 2, BOARD_HEIGHT).to_real_position(),
             ..default()
         })
+        .insert_bundle(hud_line(*board_size, 0))
         .insert(initial_lines);
+
+    let initial_combo = Combo::default();
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(initial_combo.to_string(), text_style.clone())
+                .with_alignment(text_alignment),
+            ..default()
+        })
+        .insert_bundle(hud_line(*board_size, 6))
+        .insert(initial_combo);
+
+    let initial_back_to_back = BackToBack::default();
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(initial_back_to_back.to_string(), text_style.clone())
+                .with_alignment(text_alignment),
+            ..default()
+        })
+        .insert_bundle(hud_line(*board_size, 8))
+        .insert(initial_back_to_back);
+
+    let initial_play_time = PlayTime::default();
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(initial_play_time.to_string(), text_style.clone())
+                .with_alignment(text_alignment),
+            ..default()
+        })
+        .insert_bundle(hud_line(*board_size, 10))
+        .insert(initial_play_time);
+
+    let initial_grade = MasterGrade::default();
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(initial_grade.to_string(), text_style.clone())
+                .with_alignment(text_alignment),
+            ..default()
+        })
+        .insert_bundle(hud_line(*board_size, 16))
+        .insert(initial_grade);
+
+    let initial_goal = Goal::default();
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(initial_goal.to_string(), text_style.clone())
+                .with_alignment(text_alignment),
+            ..default()
+        })
+        .insert_bundle(hud_line(*board_size, 16))
+        .insert(initial_goal);
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section("", text_style).with_alignment(text_alignment),
+            ..default()
+        })
+        .insert_bundle(hud_line(*board_size, 13))
+        .insert(ClearAnnouncement {
+            timer: Timer::from_seconds(ANNOUNCEMENT_DURATION, false),
+        });
 }
 
 pub fn dispayable_changed<T>(mut query: Query<(&T, &mut Text), Changed<T>>)
@@ -130,3 +296,34 @@ impl Display for LineCompleted {
         write!(f, "LINES\n{:03}", self.line_completed)
     }
 }
+impl Display for Combo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "COMBO\n{:02}", self.0)
+    }
+}
+impl Display for BackToBack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "B2B\n{:02}", self.0)
+    }
+}
+impl Display for PlayTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TIME\n{}", format_duration(self.0))
+    }
+}
+impl Display for MasterGrade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(grade) => write!(f, "GRADE\n{}", grade),
+            None => Ok(()),
+        }
+    }
+}
+impl Display for Goal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(goal) => write!(f, "GOAL\n{}", goal),
+            None => Ok(()),
+        }
+    }
+}
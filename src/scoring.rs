@@ -0,0 +1,277 @@
+//! How points are awarded.
+
+use crate::engine::ClearKind;
+
+/// What happened when a piece has been locked
+pub struct LockResult {
+    /// `None` when no line has been cleared and no T-spin has been done
+    pub clear_kind: Option<ClearKind>,
+    /// Level before the lines are counted
+    pub level: usize,
+    /// Number of consecutive pieces that have cleared lines before this one,
+    /// `None` when this piece did not clear any line
+    pub combo: Option<usize>,
+    /// Is this a difficult clear following another difficult clear?
+    pub back_to_back: bool,
+    /// Is the board empty after the clear?
+    pub perfect_clear: bool,
+}
+
+/// Computes the points of a game
+pub trait ScoringRule: Send + Sync {
+    /// Points for locking a piece
+    fn lock(&self, result: &LockResult) -> usize;
+    /// Points for each line the piece goes down with the soft drop
+    fn soft_drop(&self) -> usize;
+    /// Points for each line the piece goes down with a hard drop
+    fn hard_drop(&self) -> usize;
+}
+
+/// The available scoring rules
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scoring {
+    #[default]
+    Guideline,
+    Nes,
+    Sega,
+    Tgm,
+}
+
+impl Scoring {
+    pub fn rule(&self) -> Box<dyn ScoringRule> {
+        match self {
+            Scoring::Guideline => Box::new(GuidelineScoring),
+            Scoring::Nes => Box::new(NesScoring),
+            Scoring::Sega => Box::new(SegaScoring),
+            Scoring::Tgm => Box::new(TgmScoring),
+        }
+    }
+}
+
+/// Modern games scoring: T-spins, combos, back-to-back and perfect clears
+/// are rewarded
+pub struct GuidelineScoring;
+
+impl ScoringRule for GuidelineScoring {
+    fn lock(&self, result: &LockResult) -> usize {
+        let clear_kind = match result.clear_kind {
+            Some(clear_kind) => clear_kind,
+            None => return 0,
+        };
+        let mut points = match clear_kind {
+            ClearKind::Single => 100,
+            ClearKind::Double => 300,
+            ClearKind::Triple => 500,
+            ClearKind::Tetris => 800,
+            ClearKind::MiniTSpin => 100,
+            ClearKind::MiniTSpinSingle => 200,
+            ClearKind::MiniTSpinDouble => 400,
+            ClearKind::TSpin => 400,
+            ClearKind::TSpinSingle => 800,
+            ClearKind::TSpinDouble => 1200,
+            ClearKind::TSpinTriple => 1600,
+        };
+        if result.back_to_back {
+            points = points * 3 / 2;
+        }
+        points += 50 * result.combo.unwrap_or(0);
+        if result.perfect_clear {
+            points += match (clear_kind.lines(), result.back_to_back) {
+                (1, _) => 800,
+                (2, _) => 1200,
+                (3, _) => 1800,
+                (_, false) => 2000,
+                (_, true) => 3200,
+            };
+        }
+        points * result.level
+    }
+
+    fn soft_drop(&self) -> usize {
+        1
+    }
+
+    fn hard_drop(&self) -> usize {
+        2
+    }
+}
+
+/// Nintendo's NES scoring: only the number of lines matters
+pub struct NesScoring;
+
+impl ScoringRule for NesScoring {
+    fn lock(&self, result: &LockResult) -> usize {
+        let points = match result.clear_kind.map(|clear_kind| clear_kind.lines()) {
+            Some(1) => 40,
+            Some(2) => 100,
+            Some(3) => 300,
+            Some(4) => 1200,
+            _ => 0,
+        };
+        // the NES counts levels from 0
+        points * result.level
+    }
+
+    fn soft_drop(&self) -> usize {
+        1
+    }
+
+    fn hard_drop(&self) -> usize {
+        0
+    }
+}
+
+/// Sega's arcade scoring: points grow every two levels, up to 5 times
+pub struct SegaScoring;
+
+impl ScoringRule for SegaScoring {
+    fn lock(&self, result: &LockResult) -> usize {
+        let points = match result.clear_kind.map(|clear_kind| clear_kind.lines()) {
+            Some(1) => 100,
+            Some(2) => 400,
+            Some(3) => 900,
+            Some(4) => 2000,
+            _ => 0,
+        };
+        // Sega counts levels from 0
+        points * (result.level.saturating_sub(1) / 2 + 1).min(5)
+    }
+
+    fn soft_drop(&self) -> usize {
+        0
+    }
+
+    fn hard_drop(&self) -> usize {
+        0
+    }
+}
+
+/// Tetris The Grand Master scoring: the level and the lines cleared
+/// together, multiplied by the lines and the combo, and quadrupled for a
+/// perfect clear
+pub struct TgmScoring;
+
+impl ScoringRule for TgmScoring {
+    fn lock(&self, result: &LockResult) -> usize {
+        let lines = match result.clear_kind.map(|clear_kind| clear_kind.lines()) {
+            Some(lines) if lines > 0 => lines,
+            _ => return 0,
+        };
+        let mut points =
+            (result.level + lines).div_ceil(4) * lines * (result.combo.unwrap_or(0) + 1);
+        if result.perfect_clear {
+            points *= 4;
+        }
+        points
+    }
+
+    fn soft_drop(&self) -> usize {
+        1
+    }
+
+    fn hard_drop(&self) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clear without combo, back-to-back or perfect clear
+    fn clear(clear_kind: ClearKind, level: usize) -> LockResult {
+        LockResult {
+            clear_kind: Some(clear_kind),
+            level,
+            combo: Some(0),
+            back_to_back: false,
+            perfect_clear: false,
+        }
+    }
+
+    fn no_clear() -> LockResult {
+        LockResult {
+            clear_kind: None,
+            level: 5,
+            combo: None,
+            back_to_back: false,
+            perfect_clear: false,
+        }
+    }
+
+    #[test]
+    fn guideline() {
+        let rule = Scoring::Guideline.rule();
+        assert_eq!(rule.lock(&no_clear()), 0);
+        assert_eq!(rule.lock(&clear(ClearKind::Single, 1)), 100);
+        assert_eq!(rule.lock(&clear(ClearKind::Tetris, 2)), 1600);
+        assert_eq!(rule.lock(&clear(ClearKind::TSpinDouble, 1)), 1200);
+        assert_eq!(
+            rule.lock(&LockResult {
+                back_to_back: true,
+                ..clear(ClearKind::Tetris, 1)
+            }),
+            1200
+        );
+        assert_eq!(
+            rule.lock(&LockResult {
+                combo: Some(2),
+                ..clear(ClearKind::Single, 1)
+            }),
+            200
+        );
+        assert_eq!(
+            rule.lock(&LockResult {
+                back_to_back: true,
+                perfect_clear: true,
+                ..clear(ClearKind::Tetris, 1)
+            }),
+            4400
+        );
+        assert_eq!((rule.soft_drop(), rule.hard_drop()), (1, 2));
+    }
+
+    #[test]
+    fn nes() {
+        let rule = Scoring::Nes.rule();
+        assert_eq!(rule.lock(&no_clear()), 0);
+        assert_eq!(rule.lock(&clear(ClearKind::Single, 3)), 120);
+        assert_eq!(rule.lock(&clear(ClearKind::Double, 3)), 300);
+        assert_eq!(rule.lock(&clear(ClearKind::Triple, 3)), 900);
+        assert_eq!(rule.lock(&clear(ClearKind::Tetris, 3)), 3600);
+        assert_eq!((rule.soft_drop(), rule.hard_drop()), (1, 0));
+    }
+
+    #[test]
+    fn sega() {
+        let rule = Scoring::Sega.rule();
+        assert_eq!(rule.lock(&no_clear()), 0);
+        assert_eq!(rule.lock(&clear(ClearKind::Tetris, 1)), 2000);
+        // the points grow every two levels, up to 5 times
+        for (level, points) in [(1, 100), (2, 100), (3, 200), (9, 500), (20, 500)] {
+            assert_eq!(rule.lock(&clear(ClearKind::Single, level)), points);
+        }
+        assert_eq!((rule.soft_drop(), rule.hard_drop()), (0, 0));
+    }
+
+    #[test]
+    fn tgm() {
+        let rule = Scoring::Tgm.rule();
+        assert_eq!(rule.lock(&no_clear()), 0);
+        assert_eq!(rule.lock(&clear(ClearKind::Tetris, 10)), 16);
+        assert_eq!(
+            rule.lock(&LockResult {
+                combo: Some(1),
+                ..clear(ClearKind::Tetris, 10)
+            }),
+            32
+        );
+        assert_eq!(
+            rule.lock(&LockResult {
+                perfect_clear: true,
+                ..clear(ClearKind::Tetris, 10)
+            }),
+            64
+        );
+    }
+}
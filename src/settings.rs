@@ -0,0 +1,11 @@
+/// Player preferences, independent of the rules of the game
+pub struct Settings {
+    /// Show where the current piece will land
+    pub ghost_piece: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self { ghost_piece: true }
+    }
+}
@@ -1,11 +1,16 @@
+use std::time::Duration;
+
 use bevy::{
     prelude::{shape::Quad, *},
     sprite::MaterialMesh2dBundle,
 };
 
 use crate::{
-    board::{BoardPosition, BOARD_HEIGHT, BOARD_WIDTH},
-    SpawnPieceEvent,
+    board::{BoardPosition, BoardSize},
+    engine::GameEvent,
+    menu::GameSetup,
+    piece::{GhostSquare, HoldPieceSquare, NextPieceSquare, PieceSquare},
+    window_size, HOLD_BOX_LEFT,
 };
 
 /// Each item on the board is a Square: pieces are composed
@@ -30,6 +35,7 @@ pub const SQ_BORDER_WIDTH: f32 = 2.;
 #[derive(Component)]
 pub struct Wall;
 
+/// Its transform is given by [`place_on_board`]
 #[derive(Bundle)]
 struct SquareBundle {
     square: Square,
@@ -39,16 +45,36 @@ struct SquareBundle {
 }
 
 pub const SQ_TOTAL_SIZE: f32 = SQ_SIZE + SQ_BORDER_WIDTH;
-const BOARD_LEFT_X: f32 = -((BOARD_WIDTH as f32) + 9.) / 2. * SQ_TOTAL_SIZE;
-const BOARD_BOTTOM_Y: f32 = -(BOARD_HEIGHT as f32) / 2. * SQ_TOTAL_SIZE;
 
 impl BoardPosition {
-    pub fn to_real_position(&self) -> Transform {
-        Transform::from_translation(Vec3::new(
-            BOARD_LEFT_X + SQ_TOTAL_SIZE * (self.x as f32),
-            BOARD_BOTTOM_Y + SQ_TOTAL_SIZE * (self.y as f32),
-            0.,
-        ))
+    /// Position on screen when playing on a board of the given size: the
+    /// hold piece box and the top of the board stay at the same distance
+    /// from the top left corner of the window
+    pub fn to_real_position(&self, board_size: BoardSize) -> Vec2 {
+        let window = window_size(board_size);
+        Vec2::new(
+            -window.x / 2. + SQ_TOTAL_SIZE * ((self.x - HOLD_BOX_LEFT) as f32 + 1.5),
+            window.y / 2. - SQ_TOTAL_SIZE * (board_size.height + 1 - self.y) as f32,
+        )
+    }
+}
+
+/// Places on screen what has a board position, everything is placed again
+/// when the size of the board changes
+pub fn place_on_board(
+    board_size: Res<BoardSize>,
+    mut query: Query<(
+        &BoardPosition,
+        ChangeTrackers<BoardPosition>,
+        &mut Transform,
+    )>,
+) {
+    for (bp, bp_tracker, mut transform) in &mut query {
+        if board_size.is_changed() || bp_tracker.is_changed() {
+            let position = bp.to_real_position(*board_size);
+            transform.translation.x = position.x;
+            transform.translation.y = position.y;
+        }
     }
 }
 
@@ -56,10 +82,7 @@ impl SquareBundle {
     fn from_board_position(square: Square, board_position: BoardPosition) -> Self {
         Self {
             square,
-            spatial_bundle: SpatialBundle {
-                transform: board_position.to_real_position(),
-                ..Default::default()
-            },
+            spatial_bundle: SpatialBundle::default(),
             board_position,
         }
     }
@@ -79,7 +102,7 @@ pub fn spawn_square<T: Component>(
 
     entity.with_children(|commands| {
         let mut bgcolor = color.clone();
-        bgcolor.set_a(0.25);
+        bgcolor.set_a(color.a() * 0.25);
         // background
         commands.spawn_bundle(MaterialMesh2dBundle {
             mesh: meshes
@@ -190,8 +213,7 @@ pub fn disappearing_square(
     mut materials: ResMut<Assets<ColorMaterial>>,
     time: Res<Time>,
     mut sq_query: Query<(Entity, &mut DisappearingSquare, &Children)>,
-    mut query: Query<&mut Handle<ColorMaterial>>,
-    mut spawn_next_piece: EventWriter<SpawnPieceEvent>,
+    query: Query<&Handle<ColorMaterial>>,
     mut move_below: EventWriter<MoveBelowEvent>,
 ) {
     let delta = time.delta().as_secs_f32();
@@ -204,21 +226,146 @@ pub fn disappearing_square(
             ended = true;
             commands.entity(square_entity).despawn_recursive();
         } else {
-            for square_child in square_children {
-                // all children of a square have a color...
-                let cm = query.get_mut(*square_child).unwrap();
-                let color = &mut materials.get_mut(&cm).unwrap().color;
+            for_each_color(square_children, &query, &mut materials, |_, color| {
                 let new_alpha = color.a() - DISAPEARING_VELOCITY * delta;
                 if new_alpha >= 0. {
                     color.set_a(new_alpha);
                 }
-            }
+            });
         }
     }
 
     if ended {
         move_below.send_default();
-        spawn_next_piece.send_default();
+    }
+}
+
+/// Calls `f` with the index and the color of each part of a square
+fn for_each_color(
+    square_children: &Children,
+    query: &Query<&Handle<ColorMaterial>>,
+    materials: &mut Assets<ColorMaterial>,
+    mut f: impl FnMut(usize, &mut Color),
+) {
+    for (index, square_child) in square_children.iter().enumerate() {
+        // all children of a square have a color...
+        let cm = query.get(*square_child).unwrap();
+        f(index, &mut materials.get_mut(cm).unwrap().color);
+    }
+}
+
+/// Locked square of a game where the stack is hidden
+#[derive(Component)]
+pub struct FadingSquare {
+    /// time since the square has been locked
+    elapsed: Duration,
+    /// alpha of each part of the square when it was locked
+    alphas: Vec<f32>,
+    /// time since the stack has been revealed, at the end of the game
+    revealed: Option<Duration>,
+}
+
+impl FadingSquare {
+    /// How visible the square is, from 0 to 1, when it stays visible for
+    /// `delay` before fading
+    fn opacity(&self, delay: Duration) -> f32 {
+        // the revealed stack is shown for a while, then fades out again
+        if let Some(revealed) = self.revealed {
+            let fading = revealed.saturating_sub(REVEAL_DURATION).as_secs_f32();
+            return (1. - fading * FADING_VELOCITY).max(0.);
+        }
+        // invisible squares do not fade, they vanish
+        if delay.is_zero() {
+            return 0.;
+        }
+        let fading = self.elapsed.saturating_sub(delay).as_secs_f32();
+        (1. - fading * FADING_VELOCITY).max(0.)
+    }
+}
+
+const FADING_VELOCITY: f32 = 1.;
+/// How long the stack stays visible once revealed
+const REVEAL_DURATION: Duration = Duration::from_secs(2);
+
+/// Squares locked on the board that do not fade yet
+type NewlyLockedFilter = (
+    With<Square>,
+    Without<FadingSquare>,
+    Without<Wall>,
+    Without<PieceSquare>,
+    Without<NextPieceSquare>,
+    Without<HoldPieceSquare>,
+    Without<GhostSquare>,
+    Without<DisappearingSquare>,
+);
+
+/// Hides the locked squares in the modes where the stack fades, and
+/// briefly reveals the whole stack when the game is over
+#[allow(clippy::too_many_arguments)]
+pub fn fade_stack(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    time: Res<Time>,
+    setup: Res<GameSetup>,
+    // the squares of the pieces locked since the last frame
+    locked_query: Query<(Entity, &Children), NewlyLockedFilter>,
+    mut fading_query: Query<(&mut FadingSquare, &Children), Without<DisappearingSquare>>,
+    query: Query<&Handle<ColorMaterial>>,
+    mut event_reader: EventReader<GameEvent>,
+) {
+    let delay = match setup.mode.stack_fade() {
+        Some(delay) => delay,
+        None => return,
+    };
+    let game_over = event_reader
+        .iter()
+        .any(|event| matches!(event, GameEvent::GameOver(_)));
+    for (mut fading, square_children) in &mut fading_query {
+        fading.elapsed += time.delta();
+        if game_over {
+            fading.revealed = Some(Duration::ZERO);
+        }
+        let opacity = fading.opacity(delay);
+        for_each_color(square_children, &query, &mut materials, |index, color| {
+            color.set_a(fading.alphas[index] * opacity);
+        });
+    }
+    for (entity, square_children) in &locked_query {
+        let mut fading = FadingSquare {
+            elapsed: Duration::ZERO,
+            alphas: Vec::new(),
+            revealed: game_over.then_some(Duration::ZERO),
+        };
+        for_each_color(square_children, &query, &mut materials, |_, color| {
+            fading.alphas.push(color.a())
+        });
+        let opacity = fading.opacity(delay);
+        for_each_color(square_children, &query, &mut materials, |index, color| {
+            color.set_a(fading.alphas[index] * opacity);
+        });
+        commands.entity(entity).insert(fading);
+    }
+}
+
+/// Fades out the stack revealed at the end of the game, once the game is
+/// no longer updated
+pub fn hide_revealed_stack(
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    time: Res<Time>,
+    mut fading_query: Query<(&mut FadingSquare, &Children)>,
+    query: Query<&Handle<ColorMaterial>>,
+) {
+    for (mut fading, square_children) in &mut fading_query {
+        let revealed = match fading.revealed {
+            Some(revealed) => revealed + time.delta(),
+            None => continue,
+        };
+        fading.revealed = Some(revealed);
+        // the delay only matters before the stack is revealed
+        let opacity = fading.opacity(Duration::ZERO);
+        for_each_color(square_children, &query, &mut materials, |index, color| {
+            color.set_a(fading.alphas[index] * opacity);
+        });
     }
 }
 
@@ -232,13 +379,12 @@ pub struct MoveBelowEvent;
 pub fn to_move_below(
     mut commands: Commands,
     mut move_below: EventReader<MoveBelowEvent>,
-    mut query: Query<(Entity, &ToMoveBelow, &mut BoardPosition, &mut Transform)>,
+    mut query: Query<(Entity, &ToMoveBelow, &mut BoardPosition)>,
 ) {
     for _ in move_below.iter() {
         // move down
-        for (e, num_of_lines, mut bp, mut tr) in &mut query {
+        for (e, num_of_lines, mut bp) in &mut query {
             bp.y -= num_of_lines.0;
-            tr.translation.y -= num_of_lines.0 as f32 * SQ_TOTAL_SIZE;
             commands.entity(e).remove::<ToMoveBelow>();
         }
     }